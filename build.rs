@@ -1,15 +1,11 @@
-use std::io::{Write, BufWriter};
-use std::fs::File;
+use std::env;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Necessary because of this issue: https://github.com/rust-lang/cargo/issues/9641
-    embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
-    embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
-
-    // generate static info about this build
-    {
-        let mut f = BufWriter::new(File::create("src/meta.rs").unwrap());
-        writeln!(f, "pub const DEFAULT_CLIENT_ID: &'static str = \"_vm-{}\";", names::Generator::default().next().unwrap()).unwrap();
+    // (only applies to esp32 builds - the host simulator does not link against esp-idf)
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("espidf") {
+        embuild::build::CfgArgs::output_propagated("ESP_IDF")?;
+        embuild::build::LinkArgs::output_propagated("ESP_IDF")?;
     }
 
     Ok(())
@@ -0,0 +1,174 @@
+use std::sync::{Mutex, OnceLock};
+use std::fmt::Write;
+
+use serde::{Serialize, Deserialize};
+use sha2::Sha256;
+
+use netsblox_vm::json::serde_json;
+
+use crate::svc::http::server::EspHttpConnection;
+use crate::storage::StorageController;
+use crate::sys::EspError;
+
+// max number of outstanding tokens (issuing a new token past this limit revokes the oldest one)
+const MAX_TOKENS: usize = 16;
+const TOKEN_BYTES: usize = 16;
+
+// the device password is stored as "pbkdf2-sha256$<iterations>$<salt>$<hash>" (hex encoded)
+const PASSWORD_HASH_SCHEME: &str = "pbkdf2-sha256";
+const PASSWORD_HASH_ITERATIONS: u32 = 10_000;
+const PASSWORD_SALT_BYTES: usize = 16;
+const PASSWORD_HASH_BYTES: usize = 32;
+
+const PAIRING_CODE_BYTES: usize = 4;
+
+// random code printed to the serial console at boot, which must be given to set the first device password
+static PAIRING_CODE: OnceLock<String> = OnceLock::new();
+
+/// The access level granted by a token.
+/// Roles are ordered, so a token also grants access to anything that requires a lesser role.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Role {
+    /// Can pull the status/output of the running project and download the project and peripherals config.
+    ReadOnly,
+    /// Full control of the device.
+    Admin,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Token {
+    token: String,
+    role: Role,
+}
+
+pub enum Access {
+    Granted,
+    /// The request had no token, or the token was invalid.
+    Unauthenticated,
+    /// The request had a valid token, but its role was insufficient.
+    Forbidden,
+    /// The device has not been paired, so only read-only access is available.
+    Unpaired,
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |res, (a, b)| res | (a ^ b)) == 0
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(2 * bytes.len()), |mut res, x| { write!(res, "{x:02x}").unwrap(); res })
+}
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 { return None }
+    (0..hex.len()).step_by(2).map(|i| hex.get(i..i + 2).and_then(|x| u8::from_str_radix(x, 16).ok())).collect()
+}
+
+fn hash_password(password: &str, salt: &[u8], iterations: u32) -> [u8; PASSWORD_HASH_BYTES] {
+    let mut hash = [0u8; PASSWORD_HASH_BYTES];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut hash);
+    hash
+}
+
+fn load_tokens(storage: &mut StorageController) -> Result<Vec<Token>, EspError> {
+    Ok(storage.auth_tokens().get()?.and_then(|x| serde_json::from_str(&x).ok()).unwrap_or_default())
+}
+
+/// Checks if the device has been paired, i.e., a device password has been set.
+/// Unpaired devices only allow read-only access until paired with the code from [`pairing_code`].
+pub fn is_paired(storage: &Mutex<StorageController>) -> Result<bool, EspError> {
+    Ok(storage.lock().unwrap().admin_pass().get()?.is_some())
+}
+
+/// Gets the pairing code for this boot, which is required to set the first device password.
+/// This is only shown on the serial console, so that pairing requires physical access to the device.
+pub fn pairing_code() -> &'static str {
+    PAIRING_CODE.get_or_init(|| {
+        let mut bytes = [0u8; PAIRING_CODE_BYTES];
+        getrandom::fill(&mut bytes).expect("failed to generate random pairing code");
+        to_hex(&bytes)
+    })
+}
+
+/// Checks the given code against the pairing code.
+pub fn check_pairing_code(code: &str) -> bool {
+    constant_time_eq(pairing_code().as_bytes(), code.trim().as_bytes())
+}
+
+/// Sets the device password, which is stored as a salted hash.
+pub fn set_password(storage: &Mutex<StorageController>, password: &str) -> Result<(), EspError> {
+    let mut salt = [0u8; PASSWORD_SALT_BYTES];
+    getrandom::fill(&mut salt).expect("failed to generate random salt");
+    let hash = hash_password(password, &salt, PASSWORD_HASH_ITERATIONS);
+    storage.lock().unwrap().admin_pass().set(&format!("{PASSWORD_HASH_SCHEME}${PASSWORD_HASH_ITERATIONS}${}${}", to_hex(&salt), to_hex(&hash)))
+}
+
+/// Checks the given password against the device password.
+/// This always fails if the device is not paired.
+pub fn check_password(storage: &Mutex<StorageController>, password: &str) -> Result<bool, EspError> {
+    let stored = match storage.lock().unwrap().admin_pass().get()? {
+        Some(x) => x,
+        None => return Ok(false),
+    };
+    let mut parts = stored.split('$');
+    let (iterations, salt, hash) = match (parts.next(), parts.next().and_then(|x| x.parse().ok()), parts.next().and_then(from_hex), parts.next().and_then(from_hex), parts.next()) {
+        (Some(PASSWORD_HASH_SCHEME), Some(iterations), Some(salt), Some(hash), None) => (iterations, salt, hash),
+        _ => return Ok(false), // corrupted, so the device must be wiped to be paired again
+    };
+    Ok(constant_time_eq(&hash_password(password, &salt, iterations), &hash))
+}
+
+/// Issues (and stores) a new random token with the given role.
+pub fn issue_token(storage: &Mutex<StorageController>, role: Role) -> Result<String, EspError> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::fill(&mut bytes).expect("failed to generate random token");
+    let token = to_hex(&bytes);
+
+    let mut storage = storage.lock().unwrap();
+    let mut tokens = load_tokens(&mut storage)?;
+    if tokens.len() >= MAX_TOKENS {
+        tokens.drain(..=tokens.len() - MAX_TOKENS);
+    }
+    tokens.push(Token { token: token.clone(), role });
+    storage.auth_tokens().set(&serde_json::to_string(&tokens).unwrap())?;
+
+    Ok(token)
+}
+
+/// Gets the role granted by the given token, or `None` if the token is invalid.
+pub fn token_role(storage: &Mutex<StorageController>, token: &str) -> Result<Option<Role>, EspError> {
+    let tokens = load_tokens(&mut storage.lock().unwrap())?;
+    Ok(tokens.iter().find(|x| constant_time_eq(x.token.as_bytes(), token.as_bytes())).map(|x| x.role))
+}
+
+/// Revokes a single token (if it exists).
+pub fn revoke_token(storage: &Mutex<StorageController>, token: &str) -> Result<(), EspError> {
+    let mut storage = storage.lock().unwrap();
+    let mut tokens = load_tokens(&mut storage)?;
+    tokens.retain(|x| !constant_time_eq(x.token.as_bytes(), token.as_bytes()));
+    storage.auth_tokens().set(&serde_json::to_string(&tokens).unwrap())
+}
+
+/// Revokes all outstanding tokens.
+pub fn revoke_all_tokens(storage: &Mutex<StorageController>) -> Result<(), EspError> {
+    storage.lock().unwrap().auth_tokens().clear()
+}
+
+/// Gets the bearer token from the `Authorization` header of a request (if present).
+pub fn request_token<'a>(connection: &'a EspHttpConnection<'_>) -> Option<&'a str> {
+    connection.header("Authorization").and_then(|x| x.strip_prefix("Bearer ")).map(str::trim)
+}
+
+/// Checks if a request is allowed to access an endpoint which requires the given role.
+pub fn check(storage: &Mutex<StorageController>, connection: &EspHttpConnection<'_>, role: Role) -> Result<Access, EspError> {
+    if !is_paired(storage)? {
+        return Ok(if role <= Role::ReadOnly { Access::Granted } else { Access::Unpaired });
+    }
+    Ok(match request_token(connection) {
+        Some(token) => match token_role(storage, token)? {
+            Some(x) if x >= role => Access::Granted,
+            Some(_) => Access::Forbidden,
+            None => Access::Unauthenticated,
+        }
+        None => Access::Unauthenticated,
+    })
+}
@@ -0,0 +1,166 @@
+use std::net::Ipv4Addr;
+use std::ffi::{c_int, c_uchar, c_void};
+use std::sync::Mutex;
+use std::fmt;
+
+use esp_idf_sys::{self as sys, EspError};
+use esp_idf_sys::mbedtls::*;
+
+use crate::storage::StorageController;
+
+const CERT_SUBJECT: &'static str = "CN=NetsBlox VM ESP32,O=NetsBlox\0";
+const CERT_HOSTNAME: &'static str = "nb-esp32";
+const CERT_NOT_BEFORE: &'static str = "20240101000000\0";
+const CERT_NOT_AFTER: &'static str = "20491231235959\0";
+const SUBJECT_ALT_NAME_OID: &'static [u8] = &[0x55, 0x1d, 0x11];
+const PEM_BUFFER_SIZE: usize = 4096;
+
+pub struct Certificate {
+    pub cert: String,
+    pub key: String,
+}
+
+#[derive(Debug)]
+pub enum CertificateError {
+    Storage(EspError),
+    MbedTls { context: &'static str, code: c_int },
+}
+impl fmt::Display for CertificateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CertificateError::Storage(e) => write!(f, "storage error: {e}"),
+            CertificateError::MbedTls { context, code } => write!(f, "mbedtls {context} failed with code -0x{:04x}", -code),
+        }
+    }
+}
+impl From<EspError> for CertificateError {
+    fn from(value: EspError) -> Self {
+        Self::Storage(value)
+    }
+}
+
+fn check(context: &'static str, code: c_int) -> Result<(), CertificateError> {
+    match code {
+        0 => Ok(()),
+        code => Err(CertificateError::MbedTls { context, code }),
+    }
+}
+
+unsafe extern "C" fn fill_random(_: *mut c_void, buf: *mut c_uchar, len: usize) -> c_int {
+    sys::esp_fill_random(buf as *mut c_void, len);
+    0
+}
+
+fn read_pem(buf: &[u8]) -> String {
+    let len = buf.iter().position(|&x| x == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Encodes the subject alternative name extension (RFC 5280 4.2.1.6) for the given hostname and ip addresses.
+fn encode_subject_alt_names(hostname: &str, ips: &[Ipv4Addr]) -> Vec<u8> {
+    let mut names = vec![0x82, hostname.len() as u8]; // [2] dNSName
+    names.extend_from_slice(hostname.as_bytes());
+    for ip in ips {
+        names.extend_from_slice(&[0x87, 4]); // [7] iPAddress
+        names.extend_from_slice(&ip.octets());
+    }
+
+    let mut res = vec![0x30, names.len() as u8]; // SEQUENCE
+    res.extend_from_slice(&names);
+    res
+}
+
+/// Generates a new self-signed certificate (and private key) which is valid for the given ip addresses.
+pub fn generate(ips: &[Ipv4Addr]) -> Result<Certificate, CertificateError> {
+    let san = encode_subject_alt_names(CERT_HOSTNAME, ips);
+    let mut serial = [0u8; 16];
+    unsafe { sys::esp_fill_random(serial.as_mut_ptr() as *mut c_void, serial.len()) };
+    serial[0] &= 0x7f; // serial numbers must be positive
+
+    unsafe {
+        let mut key: mbedtls_pk_context = core::mem::zeroed();
+        let mut crt: mbedtls_x509write_cert = core::mem::zeroed();
+        mbedtls_pk_init(&mut key);
+        mbedtls_x509write_crt_init(&mut crt);
+
+        let res = (|| {
+            // note: ecdsa keys are used because rsa key generation takes far too long on the device
+            check("pk_setup", mbedtls_pk_setup(&mut key, mbedtls_pk_info_from_type(mbedtls_pk_type_t_MBEDTLS_PK_ECKEY)))?;
+            check("ecp_gen_key", mbedtls_ecp_gen_key(mbedtls_ecp_group_id_MBEDTLS_ECP_DP_SECP256R1, key.private_pk_ctx as *mut mbedtls_ecp_keypair, Some(fill_random), core::ptr::null_mut()))?;
+
+            mbedtls_x509write_crt_set_version(&mut crt, MBEDTLS_X509_CRT_VERSION_3 as c_int);
+            mbedtls_x509write_crt_set_md_alg(&mut crt, mbedtls_md_type_t_MBEDTLS_MD_SHA256);
+            mbedtls_x509write_crt_set_subject_key(&mut crt, &mut key);
+            mbedtls_x509write_crt_set_issuer_key(&mut crt, &mut key);
+            check("set_subject_name", mbedtls_x509write_crt_set_subject_name(&mut crt, CERT_SUBJECT.as_ptr() as _))?;
+            check("set_issuer_name", mbedtls_x509write_crt_set_issuer_name(&mut crt, CERT_SUBJECT.as_ptr() as _))?;
+            check("set_serial", mbedtls_x509write_crt_set_serial_raw(&mut crt, serial.as_mut_ptr(), serial.len()))?;
+            check("set_validity", mbedtls_x509write_crt_set_validity(&mut crt, CERT_NOT_BEFORE.as_ptr() as _, CERT_NOT_AFTER.as_ptr() as _))?;
+            check("set_basic_constraints", mbedtls_x509write_crt_set_basic_constraints(&mut crt, 0, -1))?;
+            check("set_subject_alt_name", mbedtls_x509write_crt_set_extension(&mut crt, SUBJECT_ALT_NAME_OID.as_ptr() as _, SUBJECT_ALT_NAME_OID.len(), 0, san.as_ptr(), san.len()))?;
+
+            let mut buf = vec![0u8; PEM_BUFFER_SIZE];
+            check("crt_pem", mbedtls_x509write_crt_pem(&mut crt, buf.as_mut_ptr(), buf.len(), Some(fill_random), core::ptr::null_mut()))?;
+            let cert = read_pem(&buf);
+
+            buf.fill(0);
+            check("key_pem", mbedtls_pk_write_key_pem(&mut key, buf.as_mut_ptr(), buf.len()))?;
+            let key = read_pem(&buf);
+            buf.fill(0);
+
+            Ok(Certificate { cert, key })
+        })();
+
+        mbedtls_x509write_crt_free(&mut crt);
+        mbedtls_pk_free(&mut key);
+        res
+    }
+}
+
+/// Checks that the given certificate and private key can be parsed and belong together.
+pub fn validate(cert: &str, key: &str) -> Result<(), CertificateError> {
+    let cert = format!("{cert}\0");
+    let key = format!("{key}\0");
+
+    unsafe {
+        let mut crt: mbedtls_x509_crt = core::mem::zeroed();
+        let mut pk: mbedtls_pk_context = core::mem::zeroed();
+        mbedtls_x509_crt_init(&mut crt);
+        mbedtls_pk_init(&mut pk);
+
+        let res = (|| {
+            check("parse_crt", mbedtls_x509_crt_parse(&mut crt, cert.as_ptr(), cert.len()))?;
+            check("parse_key", mbedtls_pk_parse_key(&mut pk, key.as_ptr(), key.len(), core::ptr::null(), 0, Some(fill_random), core::ptr::null_mut()))?;
+            check("check_pair", mbedtls_pk_check_pair(&mut crt.pk, &mut pk, Some(fill_random), core::ptr::null_mut()))?;
+            Ok(())
+        })();
+
+        mbedtls_pk_free(&mut pk);
+        mbedtls_x509_crt_free(&mut crt);
+        res
+    }
+}
+
+/// Gets the stored https certificate, generating (and storing) a new one if there is none or if it is corrupted.
+/// Auto-generated certificates are also regenerated if the device ip addresses have changed, but custom (uploaded) certificates are kept as-is.
+pub fn get_or_generate(storage: &Mutex<StorageController>, ips: &[Ipv4Addr]) -> Result<Certificate, CertificateError> {
+    let san = ips.iter().map(ToString::to_string).collect::<Vec<_>>().join(",");
+    let mut storage = storage.lock().unwrap();
+
+    if let (Some(cert), Some(key)) = (storage.tls_cert().get()?, storage.tls_key().get()?) {
+        let auto_san = storage.tls_auto_san().get()?;
+        match validate(&cert, &key) {
+            Ok(()) => if auto_san.is_none() || auto_san.as_deref() == Some(san.as_str()) {
+                return Ok(Certificate { cert, key });
+            }
+            Err(e) => println!("stored https certificate is invalid ({e}) -- generating a new one"),
+        }
+    }
+
+    println!("generating https certificate for {san}...");
+    let res = generate(ips)?;
+    storage.tls_cert().set(&res.cert)?;
+    storage.tls_key().set(&res.key)?;
+    storage.tls_auto_san().set(&san)?;
+    Ok(res)
+}
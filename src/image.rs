@@ -0,0 +1,168 @@
+//! Decoding of costume images into small rgb bitmaps for led matrices and displays.
+//!
+//! Costumes can be much larger than the devices they are shown on (and than the free heap),
+//! so png images are downscaled row by row as they are decoded, and jpeg images are decoded at a reduced scale.
+
+use std::fmt;
+
+const PNG_MAGIC: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_MAGIC: &[u8] = &[0xff, 0xd8];
+
+// interlaced pngs have to be decoded in full (at up to 4 bytes per pixel), so only allow small ones
+const MAX_INTERLACED_PNG_PIXELS: usize = 128 * 128;
+
+#[derive(Debug)]
+pub enum ImageError {
+    UnsupportedFormat,
+    TooLarge { width: usize, height: usize },
+    Png(png::DecodingError),
+    Jpeg(jpeg_decoder::Error),
+}
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::UnsupportedFormat => write!(f, "unsupported image format (expected png or jpeg)"),
+            ImageError::TooLarge { width, height } => write!(f, "image is too large ({width}x{height}), try a smaller or non-interlaced png"),
+            ImageError::Png(e) => write!(f, "invalid png: {e}"),
+            ImageError::Jpeg(e) => write!(f, "invalid jpeg: {e}"),
+        }
+    }
+}
+impl From<png::DecodingError> for ImageError { fn from(value: png::DecodingError) -> Self { Self::Png(value) } }
+impl From<jpeg_decoder::Error> for ImageError { fn from(value: jpeg_decoder::Error) -> Self { Self::Jpeg(value) } }
+
+/// Resizes an image to a fixed size by averaging all the source pixels that overlap each target pixel.
+/// Pixels are premultiplied by their alpha, so transparent regions come out black (i.e., off).
+struct Resizer {
+    width: usize,
+    height: usize,
+    src_width: usize,
+    src_height: usize,
+    sums: Vec<[u32; 4]>, // r, g, b, count
+}
+impl Resizer {
+    fn new(width: usize, height: usize, src_width: usize, src_height: usize) -> Self {
+        Self { width, height, src_width, src_height, sums: vec![[0; 4]; width * height] }
+    }
+    /// Gets the range of target pixels that overlap the given source pixel (along one axis).
+    fn targets(src: usize, src_size: usize, size: usize) -> std::ops::RangeInclusive<usize> {
+        (src * size / src_size)..=(((src + 1) * size - 1) / src_size)
+    }
+    fn add_row(&mut self, src_y: usize, row: impl Iterator<Item = [u8; 4]>) {
+        if src_y >= self.src_height { return }
+        for (src_x, [r, g, b, a]) in row.take(self.src_width).enumerate() {
+            let premultiply = |x: u8| x as u32 * a as u32 / 255;
+            for y in Self::targets(src_y, self.src_height, self.height) {
+                for x in Self::targets(src_x, self.src_width, self.width) {
+                    let sum = &mut self.sums[y * self.width + x];
+                    sum[0] += premultiply(r);
+                    sum[1] += premultiply(g);
+                    sum[2] += premultiply(b);
+                    sum[3] += 1;
+                }
+            }
+        }
+    }
+    fn finish(self) -> Vec<[u8; 3]> {
+        self.sums.into_iter().map(|[r, g, b, n]| match n {
+            0 => [0; 3],
+            n => [(r / n) as u8, (g / n) as u8, (b / n) as u8],
+        }).collect()
+    }
+}
+
+fn to_rgba(data: &[u8], channels: usize) -> impl Iterator<Item = [u8; 4]> + '_ {
+    data.chunks_exact(channels).map(move |x| match *x {
+        [l] => [l, l, l, 255],
+        [l, a] => [l, l, l, a],
+        [r, g, b] => [r, g, b, 255],
+        [r, g, b, a] => [r, g, b, a],
+        _ => unreachable!(),
+    })
+}
+
+fn decode_png(content: &[u8], width: usize, height: usize) -> Result<Vec<[u8; 3]>, ImageError> {
+    let mut decoder = png::Decoder::new(content);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let channels = reader.output_color_type().0.samples();
+    let (src_width, src_height, interlaced) = {
+        let info = reader.info();
+        (info.width as usize, info.height as usize, info.interlaced)
+    };
+
+    let mut res = Resizer::new(width, height, src_width, src_height);
+    if interlaced {
+        // interlaced rows come out of order, so just decode the whole thing (these are rare)
+        if src_width.saturating_mul(src_height) > MAX_INTERLACED_PNG_PIXELS {
+            return Err(ImageError::TooLarge { width: src_width, height: src_height });
+        }
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        for (y, row) in buf.chunks_exact(info.line_size).enumerate() {
+            res.add_row(y, to_rgba(row, channels));
+        }
+    } else {
+        let mut y = 0;
+        while let Some(row) = reader.next_row()? {
+            res.add_row(y, to_rgba(row.data(), channels));
+            y += 1;
+        }
+    }
+    Ok(res.finish())
+}
+
+fn decode_jpeg(content: &[u8], width: usize, height: usize) -> Result<Vec<[u8; 3]>, ImageError> {
+    let mut decoder = jpeg_decoder::Decoder::new(content);
+    decoder.read_info()?;
+    decoder.scale(width.min(u16::MAX as usize) as u16, height.min(u16::MAX as usize) as u16)?; // decode at the smallest scale that is still at least the target size
+    let data = decoder.decode()?;
+    let info = decoder.info().unwrap(); // info is always available after decoding
+
+    let mut res = Resizer::new(width, height, info.width as usize, info.height as usize);
+    let pixel_bytes = info.pixel_format.pixel_bytes();
+    for (y, row) in data.chunks_exact(info.width as usize * pixel_bytes).enumerate() {
+        match info.pixel_format {
+            jpeg_decoder::PixelFormat::L8 | jpeg_decoder::PixelFormat::RGB24 => res.add_row(y, to_rgba(row, pixel_bytes)),
+            jpeg_decoder::PixelFormat::L16 => res.add_row(y, row.chunks_exact(2).map(|x| [x[0], x[0], x[0], 255])), // big endian, so keep the high byte
+            jpeg_decoder::PixelFormat::CMYK32 => res.add_row(y, row.chunks_exact(4).map(|x| {
+                let cvt = |c: u8| ((255 - c as u32) * (255 - x[3] as u32) / 255) as u8;
+                [cvt(x[0]), cvt(x[1]), cvt(x[2]), 255]
+            })),
+        }
+    }
+    Ok(res.finish())
+}
+
+/// Decodes a png or jpeg image and resizes it to exactly `width` x `height` pixels (ignoring the aspect ratio).
+/// The result is in row-major order.
+pub fn decode_rgb(content: &[u8], width: usize, height: usize) -> Result<Vec<[u8; 3]>, ImageError> {
+    if content.starts_with(PNG_MAGIC) {
+        decode_png(content, width, height)
+    } else if content.starts_with(JPEG_MAGIC) {
+        decode_jpeg(content, width, height)
+    } else {
+        Err(ImageError::UnsupportedFormat)
+    }
+}
+
+/// Converts pixels to black and white (where `true` is white) using Floyd-Steinberg dithering.
+pub fn dither_monochrome(pixels: &[[u8; 3]], width: usize) -> Vec<bool> {
+    let height = pixels.len() / width;
+    let mut luma: Vec<i16> = pixels.iter().map(|&[r, g, b]| ((r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000) as i16).collect();
+    let mut res = vec![false; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            res[i] = luma[i] >= 128;
+            let error = luma[i] - if res[i] { 255 } else { 0 };
+            for (dx, dy, weight) in [(1, 0, 7), (-1, 1, 3), (0, 1, 5), (1, 1, 1)] {
+                let (x, y) = (x as isize + dx, y + dy);
+                if x >= 0 && (x as usize) < width && y < height {
+                    luma[y * width + x as usize] += error * weight / 16;
+                }
+            }
+        }
+    }
+    res
+}
@@ -1,36 +1,39 @@
-#![feature(concat_bytes)]
-
 use std::collections::VecDeque;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::mpsc::{channel, Sender};
+use std::time::{Duration, Instant};
+use std::net::Ipv4Addr;
 use std::fmt::Write;
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::thread;
 
-use esp_idf_svc::http::server::{EspHttpServer, EspHttpConnection, Configuration};
-use esp_idf_svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
-use esp_idf_svc::eventloop::EspSystemEventLoop;
-use esp_idf_svc::tls::X509;
-use esp_idf_svc::sntp::{EspSntp, SyncStatus, SyncMode, SntpConf};
+use crate::svc::http::server::{EspHttpServer, EspHttpConnection, Configuration};
+use crate::svc::nvs::{EspDefaultNvs, EspDefaultNvsPartition};
+use crate::svc::eventloop::EspSystemEventLoop;
+use crate::svc::sntp::{EspSntp, SyncStatus, SyncMode, SntpConf};
+
+use crate::hal::modem::WifiModem;
 
-use esp_idf_hal::modem::WifiModem;
+use crate::sys::EspError;
 
-use esp_idf_sys::EspError;
+#[cfg(target_os = "espidf")] use esp_idf_svc::tls::X509;
+#[cfg(target_os = "espidf")] use embedded_svc::http::server::Handler;
+#[cfg(not(target_os = "espidf"))] use crate::svc::http::server::Handler;
 
-use embedded_svc::http::server::Handler;
 use embedded_svc::http::Method;
 
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
 
 use string_ring::{StringRing, Granularity};
 
-use netsblox_vm::template::{ExtensionArgs, EMPTY_PROJECT};
+use netsblox_vm::template::{ExtensionArgs, SyscallMenu, EMPTY_PROJECT};
 use netsblox_vm::process::ErrorSummary;
 use netsblox_vm::project::{Input, Project, IdleAction, ProjectStep};
 use netsblox_vm::bytecode::{ByteCode, Locations, CompileError};
 use netsblox_vm::gc::{Collect, Gc, RefLock, Rootable, Arena};
 use netsblox_vm::json::serde_json;
-use netsblox_vm::runtime::{System, Config, Command, CommandStatus, CustomTypes, Key};
+use netsblox_vm::runtime::{System, Config, Command, CommandStatus, RequestStatus, CustomTypes, Key, IncomingMessage};
 use netsblox_vm::ast;
 use netsblox_vm::std_util::Clock;
 use netsblox_vm::real_time::UtcOffset;
@@ -39,27 +42,49 @@ pub use netsblox_vm;
 
 pub mod storage;
 pub mod system;
-pub mod wifi;
-pub mod http;
 pub mod platform;
-mod meta;
+pub mod auth;
+pub mod image;
+
+#[cfg(target_os = "espidf")] pub mod wifi;
+#[cfg(target_os = "espidf")] pub mod http;
+#[cfg(target_os = "espidf")] pub mod ota;
+#[cfg(target_os = "espidf")] pub mod cert;
+#[cfg(target_os = "espidf")] use {esp_idf_sys as sys, esp_idf_hal as hal, esp_idf_svc as svc};
+
+#[cfg(not(target_os = "espidf"))] pub mod sim;
+#[cfg(not(target_os = "espidf"))] pub use sim::{wifi, http};
+#[cfg(not(target_os = "espidf"))] use sim::{sys, hal, svc};
 
 use crate::storage::*;
 use crate::system::*;
 use crate::wifi::*;
+use crate::auth::{Role, Access};
 
-const CLOUD_URL: &'static str = "https://cloud.netsblox.org";
-const EDITOR_URL: &'static str = "https://editor.netsblox.org";
+const DEFAULT_CLOUD_URL: &'static str = "https://cloud.netsblox.org";
+const DEFAULT_EDITOR_URL: &'static str = "https://editor.netsblox.org";
 
 const YIELDS_BEFORE_IDLE_SLEEP: usize = 256;
 const IDLE_SLEEP_TIME: Duration = Duration::from_millis(1); // sleep clock has 1ms precision (minimum value before no-op)
 const STEP_BATCH_SIZE: usize = 128;
 const STEPS_BETWEEN_GC: usize = 1024;
+const SNTP_TIMEOUT: Duration = Duration::from_secs(10);
+const I2C_SCAN_TIMEOUT: Duration = Duration::from_secs(10); // the scan runs on the vm thread, so it has to wait for the current step batch
 
 // max size of output and error (circular) buffers between status polls
 const OUTPUT_BUFFER_SIZE: usize = 32 * 1024;
 const ERROR_BUFFER_SIZE: usize = 32 * 1024;
 
+#[cfg(target_os = "espidf")] const OTA_BUFFER_SIZE: usize = 4 * 1024;
+#[cfg(target_os = "espidf")] const OTA_REBOOT_DELAY: Duration = Duration::from_secs(1);
+#[cfg(target_os = "espidf")] const ESP_IMAGE_MAGIC: u8 = 0xe9; // first byte of every esp app image header
+
+// placeholder in the rendered extension which is replaced by the token the extension was requested with
+const EXTENSION_TOKEN_PLACEHOLDER: &'static str = "%%%AUTH_TOKEN%%%";
+
+// origin allowed to make cross-origin requests to the server (i.e., the configured editor), set once at startup
+static CORS_ORIGIN: OnceLock<String> = OnceLock::new();
+
 #[derive(Collect)]
 #[collect(no_drop, bound = "")]
 struct Env<'gc, C: CustomTypes<S>, S: System<C>> {
@@ -68,6 +93,40 @@ struct Env<'gc, C: CustomTypes<S>, S: System<C>> {
 }
 type EnvArena<C, S> = Arena<Rootable![Env<'_, C, S>]>;
 
+/// Inserts code into the rendered extension immediately before the given anchor.
+/// The anchor must occur exactly once, as anything else means the extension template changed and the injection would silently do nothing (or the wrong thing).
+fn inject_before(extension: String, anchor: &str, code: &str) -> String {
+    assert_eq!(extension.matches(anchor).count(), 1, "failed to inject into extension: expected exactly one {anchor:?}");
+    extension.replacen(anchor, &format!("{code}{anchor}"), 1)
+}
+
+/// Gets the base url of the local server when accessed via the given ip.
+#[cfg(target_os = "espidf")]
+fn server_url(ip: Ipv4Addr) -> String {
+    format!("https://{ip}")
+}
+/// Gets the base url of the local server when accessed via the given ip.
+/// The simulator serves plain http on a configurable port (see [`sim::http_port`]).
+#[cfg(not(target_os = "espidf"))]
+fn server_url(ip: Ipv4Addr) -> String {
+    format!("http://{ip}:{}", sim::http_port())
+}
+
+/// Gets the origin (scheme, host, and port) of the given url.
+fn url_origin(url: &str) -> &str {
+    match url.find("://") {
+        Some(i) => match url[i + 3..].find('/') {
+            Some(j) => &url[..i + 3 + j],
+            None => url,
+        }
+        None => url,
+    }
+}
+/// Gets the value to use for the `Access-Control-Allow-Origin` header of all responses.
+fn cors_origin() -> &'static str {
+    CORS_ORIGIN.get().map(String::as_str).unwrap_or(url_origin(DEFAULT_EDITOR_URL))
+}
+
 fn get_env<C: CustomTypes<S>, S: System<C>>(role: &ast::Role, system: Rc<S>) -> Result<EnvArena<C, S>, CompileError> {
     let (bytecode, init_info, locs, _) = ByteCode::compile(role).unwrap();
     Ok(EnvArena::new(|mc| {
@@ -103,12 +162,31 @@ fn read_all(connection: &mut EspHttpConnection<'_>) -> Result<Vec<u8>, EspError>
     Ok(res)
 }
 
+/// Checks that a request is allowed to access an endpoint which requires the given role.
+/// If not, this responds with an error and returns false, in which case the handler should return immediately.
+fn authorize(storage: &Mutex<StorageController>, connection: &mut EspHttpConnection<'_>, role: Role) -> Result<bool, EspError> {
+    let (status, msg): (u16, &[u8]) = match auth::check(storage, connection, role)? {
+        Access::Granted => return Ok(true),
+        Access::Unauthenticated => (401, b"ERROR: this device is password protected... log in from the control panel first"),
+        Access::Forbidden => (403, b"ERROR: read-only access does not allow this operation"),
+        Access::Unpaired => (401, b"ERROR: this device has not been paired... set a device password from the control panel first"),
+    };
+    connection.initiate_response(status, None, &[
+        ("Access-Control-Allow-Origin", cors_origin()),
+        ("Content-Type", "text/plain"),
+    ])?;
+    connection.write(msg)?;
+    Ok(false)
+}
+
 struct CorsOptionsHandler;
 impl Handler<EspHttpConnection<'_>> for CorsOptionsHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Access-Control-Allow-Methods", "GET, POST, OPTIONS"),
+            ("Access-Control-Allow-Headers", "Authorization, Content-Type"),
             ("Content-Type", "text/plain"),
         ])?;
         connection.write(b"")?;
@@ -117,49 +195,63 @@ impl Handler<EspHttpConnection<'_>> for CorsOptionsHandler {
 }
 
 struct RootHandler {
-    content: String,
+    content: Arc<Mutex<String>>, // includes the peripherals status, so it is replaced when the peripherals are reloaded
 }
 impl Handler<EspHttpConnection<'_>> for RootHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/html"),
         ])?;
-        connection.write(self.content.as_bytes())?;
+        let content = self.content.lock().unwrap().clone();
+        connection.write(content.as_bytes())?;
         Ok(())
     }
 }
 
 struct ExtensionHandler {
-    extension: String,
+    storage: Arc<Mutex<StorageController>>,
+    extension: Arc<Mutex<String>>, // replaced when the peripherals are reloaded
 }
 impl Handler<EspHttpConnection<'_>> for ExtensionHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        // the editor can't attach headers when loading an extension, so the token is passed as a query parameter instead
+        let token = connection.uri().split_once('?').and_then(|(_, query)| query.split('&').find_map(|x| x.strip_prefix("token="))).unwrap_or_default().to_owned();
+        let token = match auth::token_role(&self.storage, &token)? {
+            Some(_) => token,
+            None => String::new(),
+        };
+
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "application/javascript"),
         ])?;
-        connection.write(self.extension.as_bytes())?;
+        let extension = self.extension.lock().unwrap().replace(EXTENSION_TOKEN_PLACEHOLDER, &token);
+        connection.write(extension.as_bytes())?;
         Ok(())
     }
 }
 
 struct PullStatusHandler {
+    storage: Arc<Mutex<StorageController>>,
     runtime: Arc<Mutex<RuntimeContext>>,
 }
 impl Handler<EspHttpConnection<'_>> for PullStatusHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
-        println!("free memory: {:?}", unsafe { (esp_idf_sys::esp_get_free_heap_size(), esp_idf_sys::esp_get_free_internal_heap_size()) });
+        if !authorize(&self.storage, connection, Role::ReadOnly)? { return Ok(()) }
+
+        println!("free memory: {:?}", unsafe { (sys::esp_get_free_heap_size(), sys::esp_get_free_internal_heap_size()) });
 
         let res = {
             let mut runtime = self.runtime.lock().unwrap();
 
             let mut res = String::with_capacity(256 + runtime.output.len() + runtime.errors.len());
             let running = runtime.running;
-            write!(res, r#"{{"running":{:?},"output":{:?},"errors":["#, running, runtime.output.make_contiguous()).unwrap();
+            let connection = runtime.connection.lock().unwrap().as_str();
+            write!(res, r#"{{"running":{:?},"connection":{:?},"output":{:?},"errors":["#, running, connection, runtime.output.make_contiguous()).unwrap();
             let mut errors = runtime.errors.make_contiguous().lines();
             if let Some(error) = errors.next() {
                 res.push_str(error);
@@ -177,7 +269,7 @@ impl Handler<EspHttpConnection<'_>> for PullStatusHandler {
         };
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "application/json"),
         ])?;
         connection.write(res.as_bytes())?;
@@ -191,11 +283,13 @@ struct GetProjectHandler {
 impl Handler<EspHttpConnection<'_>> for GetProjectHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::ReadOnly)? { return Ok(()) }
+
         let project = self.storage.lock().unwrap().project().get()?;
         let project = project.as_deref().unwrap_or(EMPTY_PROJECT);
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "application/octet-stream"),
             ("Content-Disposition", "attachment; filename=project.xml"),
         ])?;
@@ -205,16 +299,19 @@ impl Handler<EspHttpConnection<'_>> for GetProjectHandler {
 }
 
 struct SetProjectHandler {
+    storage: Arc<Mutex<StorageController>>,
     runtime: Arc<Mutex<RuntimeContext>>,
 }
 impl Handler<EspHttpConnection<'_>> for SetProjectHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         let xml = match String::from_utf8(read_all(connection)?) {
             Ok(x) => x,
             Err(_) => {
                 connection.initiate_response(400, None, &[
-                    ("Access-Control-Allow-Origin", "*"),
+                    ("Access-Control-Allow-Origin", cors_origin()),
                     ("Content-Type", "text/plain"),
                 ])?;
                 connection.write(b"failed to parse request body")?;
@@ -225,7 +322,7 @@ impl Handler<EspHttpConnection<'_>> for SetProjectHandler {
         self.runtime.lock().unwrap().commands.push_back(ServerCommand::SetProject(xml));
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/plain"),
         ])?;
         connection.write(b"loaded project")?;
@@ -239,11 +336,13 @@ struct GetPeripheralsHandler {
 impl Handler<EspHttpConnection<'_>> for GetPeripheralsHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::ReadOnly)? { return Ok(()) }
+
         let peripherals = self.storage.lock().unwrap().peripherals().get()?;
         let peripherals = peripherals.as_deref().unwrap_or("{}");
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "application/json"),
         ])?;
         connection.write(peripherals.as_bytes())?;
@@ -251,17 +350,34 @@ impl Handler<EspHttpConnection<'_>> for GetPeripheralsHandler {
     }
 }
 
+struct PeripheralsSchemaHandler;
+impl Handler<EspHttpConnection<'_>> for PeripheralsSchemaHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        // the schema is the same for every board, so no authorization is needed (e.g., for editors to fetch it directly)
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "application/schema+json"),
+        ])?;
+        connection.write(platform::config_schema().as_bytes())?;
+        Ok(())
+    }
+}
+
 struct SetPeripheralsHandler {
     storage: Arc<Mutex<StorageController>>,
+    runtime: Arc<Mutex<RuntimeContext>>,
 }
 impl Handler<EspHttpConnection<'_>> for SetPeripheralsHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         let content = match String::from_utf8(read_all(connection)?) {
             Ok(x) => x,
             Err(_) => {
                 connection.initiate_response(400, None, &[
-                    ("Access-Control-Allow-Origin", "*"),
+                    ("Access-Control-Allow-Origin", cors_origin()),
                     ("Content-Type", "text/plain"),
                 ])?;
                 connection.write(b"failed to parse request body")?;
@@ -269,30 +385,115 @@ impl Handler<EspHttpConnection<'_>> for SetPeripheralsHandler {
             }
         };
 
+        // invalid configs are rejected unless explicitly forced (e.g., to keep a config that only fails on this board's hardware)
+        let force = connection.uri().split_once('?').is_some_and(|(_, query)| query.split('&').any(|x| x == "force=true"));
+        let errors = platform::validate_config(&content);
+        if !errors.is_empty() && !force {
+            connection.initiate_response(400, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "application/json"),
+            ])?;
+            connection.write(serde_json::to_string(&ValidationResult { valid: false, errors }).unwrap().as_bytes())?;
+            return Ok(());
+        }
+
         self.storage.lock().unwrap().peripherals().set(&content)?;
+        self.runtime.lock().unwrap().commands.push_back(ServerCommand::ReloadPeripherals);
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/plain"),
         ])?;
-        connection.write(b"successfully updated peripherals config... restart the board to apply changes...")?;
+        connection.write(b"successfully updated peripherals config... reloading peripherals (refresh the editor to update the extension blocks)...")?;
+        Ok(())
+    }
+}
+
+struct I2cScanHandler {
+    storage: Arc<Mutex<StorageController>>,
+    runtime: Arc<Mutex<RuntimeContext>>,
+}
+impl Handler<EspHttpConnection<'_>> for I2cScanHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        // the i2c buses belong to the vm thread, so ask it to do the scan for us
+        let (sender, receiver) = channel();
+        self.runtime.lock().unwrap().commands.push_back(ServerCommand::I2cScan(sender));
+        let res = match receiver.recv_timeout(I2C_SCAN_TIMEOUT) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(503, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: timed out waiting for i2c scan")?;
+                return Ok(());
+            }
+        };
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "application/json"),
+        ])?;
+        connection.write(serde_json::to_string(&res).unwrap().as_bytes())?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ValidationResult {
+    valid: bool,
+    errors: Vec<platform::ConfigError>,
+}
+
+struct ValidatePeripheralsHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for ValidatePeripheralsHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::ReadOnly)? { return Ok(()) }
+
+        let content = match String::from_utf8(read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        let errors = platform::validate_config(&content);
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "application/json"),
+        ])?;
+        connection.write(serde_json::to_string(&ValidationResult { valid: errors.is_empty(), errors }).unwrap().as_bytes())?;
         Ok(())
     }
 }
 
 struct InputHandler {
+    storage: Arc<Mutex<StorageController>>,
     runtime: Arc<Mutex<RuntimeContext>>,
 }
 impl Handler<EspHttpConnection<'_>> for InputHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         let input = match String::from_utf8(read_all(connection)?) {
             Ok(x) => match x.as_str() {
                 "start" => Input::Start,
                 "stop" => Input::Stop,
                 _ => {
                     connection.initiate_response(400, None, &[
-                        ("Access-Control-Allow-Origin", "*"),
+                        ("Access-Control-Allow-Origin", cors_origin()),
                         ("Content-Type", "text/plain"),
                     ])?;
                     connection.write(b"unknown input sequence")?;
@@ -301,7 +502,7 @@ impl Handler<EspHttpConnection<'_>> for InputHandler {
             },
             Err(_) => {
                 connection.initiate_response(400, None, &[
-                    ("Access-Control-Allow-Origin", "*"),
+                    ("Access-Control-Allow-Origin", cors_origin()),
                     ("Content-Type", "text/plain"),
                 ])?;
                 connection.write(b"failed to parse request body")?;
@@ -312,7 +513,7 @@ impl Handler<EspHttpConnection<'_>> for InputHandler {
         self.runtime.lock().unwrap().commands.push_back(ServerCommand::Input(input));
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/plain"),
         ])?;
         connection.write(b"accepted input")?;
@@ -321,15 +522,18 @@ impl Handler<EspHttpConnection<'_>> for InputHandler {
 }
 
 struct TogglePausedHandler {
+    storage: Arc<Mutex<StorageController>>,
     runtime: Arc<Mutex<RuntimeContext>>,
 }
 impl Handler<EspHttpConnection<'_>> for TogglePausedHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         self.runtime.lock().unwrap().running ^= true;
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("ContentType", "text/plain"),
         ])?;
         connection.write(b"toggled pause state")?;
@@ -343,13 +547,15 @@ struct WipeHandler {
 impl Handler<EspHttpConnection<'_>> for WipeHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         {
             let mut storage = self.storage.lock().unwrap();
             storage.clear_all()?;
         }
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/plain"),
         ])?;
         connection.write(b"wiped all data... restart the board to apply changes...")?;
@@ -373,11 +579,13 @@ struct WifiConfigHandler {
 impl Handler<EspHttpConnection<'_>> for WifiConfigHandler {
     type Error = EspError;
     fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
         let WifiConfig { kind, ssid, pass } = match serde_json::from_slice::<WifiConfig>(&read_all(connection)?) {
             Ok(x) => x,
             Err(_) => {
                 connection.initiate_response(400, None, &[
-                    ("Access-Control-Allow-Origin", "*"),
+                    ("Access-Control-Allow-Origin", cors_origin()),
                     ("Content-Type", "text/plain"),
                 ])?;
                 connection.write(b"ERROR: failed to parse request body")?;
@@ -387,7 +595,7 @@ impl Handler<EspHttpConnection<'_>> for WifiConfigHandler {
 
         if !(2..32).contains(&ssid.len()) || !(8..64).contains(&pass.len()) {
             connection.initiate_response(400, None, &[
-                ("Access-Control-Allow-Origin", "*"),
+                ("Access-Control-Allow-Origin", cors_origin()),
                 ("Content-Type", "text/plain"),
             ])?;
             connection.write(b"ERROR: ssid or password had invalid length")?;
@@ -409,7 +617,7 @@ impl Handler<EspHttpConnection<'_>> for WifiConfigHandler {
         }
 
         connection.initiate_response(200, None, &[
-            ("Access-Control-Allow-Origin", "*"),
+            ("Access-Control-Allow-Origin", cors_origin()),
             ("Content-Type", "text/plain"),
         ])?;
         connection.write(b"successfully updated wifi config... restart the board to apply changes...")?;
@@ -417,9 +625,491 @@ impl Handler<EspHttpConnection<'_>> for WifiConfigHandler {
     }
 }
 
+#[derive(Deserialize)]
+enum UrlKind {
+    Cloud, Editor, Services,
+}
+#[derive(Deserialize)]
+struct UrlConfig {
+    kind: UrlKind,
+    url: Option<String>, // none resets to default
+}
+/// Gets the default client id of this device, which is derived from the chip's MAC address.
+fn default_client_id() -> Result<String, EspError> {
+    let mut mac = [0u8; 6];
+    if let Some(e) = EspError::from(unsafe { sys::esp_efuse_mac_get_default(mac.as_mut_ptr()) }) {
+        return Err(e);
+    }
+    Ok(mac.iter().fold("_vm-".to_owned(), |mut res, x| { write!(res, "{x:02x}").unwrap(); res }))
+}
+/// Gets the client id of this device, generating (and storing) the default client id on first boot.
+fn get_client_id(storage: &Mutex<StorageController>) -> Result<String, EspError> {
+    let mut storage = storage.lock().unwrap();
+    if let Some(client_id) = storage.client_id().get()? {
+        return Ok(client_id);
+    }
+    let client_id = default_client_id()?;
+    storage.client_id().set(&client_id)?;
+    Ok(client_id)
+}
+
+#[derive(Deserialize)]
+struct ClientIdConfig {
+    id: Option<String>, // none resets to default
+}
+struct ClientIdConfigHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for ClientIdConfigHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        let ClientIdConfig { id } = match serde_json::from_slice::<ClientIdConfig>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        let id = match id {
+            Some(id) => id.trim().to_owned(),
+            None => default_client_id()?,
+        };
+        if !id.starts_with('_') || !(4..64).contains(&id.len()) || !id.chars().all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-')) {
+            connection.initiate_response(400, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(b"ERROR: client id must start with an underscore and contain only letters, digits, underscores, and dashes")?;
+            return Ok(());
+        }
+
+        self.storage.lock().unwrap().client_id().set(&id)?;
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(format!("successfully updated client id to {id}... restart the board to apply changes...").as_bytes())?;
+        Ok(())
+    }
+}
+
+struct UrlConfigHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for UrlConfigHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        let UrlConfig { kind, url } = match serde_json::from_slice::<UrlConfig>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        let url = url.map(|x| x.trim().trim_end_matches('/').to_owned());
+        if let Some(url) = &url {
+            let valid = (url.starts_with("http://") || url.starts_with("https://"))
+                && (10..256).contains(&url.len())
+                && !url.contains(|ch: char| ch.is_whitespace() || matches!(ch, '"' | '\'' | '<' | '>' | '?' | '#'));
+            if !valid {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: url must be an http or https address with no query string")?;
+                return Ok(());
+            }
+        }
+
+        {
+            let mut storage = self.storage.lock().unwrap();
+            let mut entry = match kind {
+                UrlKind::Cloud => storage.cloud_url(),
+                UrlKind::Editor => storage.editor_url(),
+                UrlKind::Services => storage.services_url(),
+            };
+            match &url {
+                Some(url) => entry.set(url)?,
+                None => entry.clear()?,
+            }
+        }
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(b"successfully updated url config... restart the board to apply changes...")?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct PasswordConfig {
+    current: Option<String>,
+    new: String,
+}
+struct PasswordConfigHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for PasswordConfigHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        let PasswordConfig { current, new } = match serde_json::from_slice::<PasswordConfig>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        if !(8..64).contains(&new.len()) {
+            connection.initiate_response(400, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(b"ERROR: password had invalid length")?;
+            return Ok(());
+        }
+
+        // until the device is paired, the pairing code from the serial console takes the place of the current password
+        let current = current.as_deref().unwrap_or_default();
+        let (valid, msg): (bool, &[u8]) = match auth::is_paired(&self.storage)? {
+            true => (auth::check_password(&self.storage, current)?, b"ERROR: current password was incorrect"),
+            false => (auth::check_pairing_code(current), b"ERROR: pairing code was incorrect"),
+        };
+        if !valid {
+            connection.initiate_response(403, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(msg)?;
+            return Ok(());
+        }
+
+        // changing the password logs out everyone (in case the old password was compromised)
+        auth::set_password(&self.storage, &new)?;
+        auth::revoke_all_tokens(&self.storage)?;
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(b"successfully updated device password")?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct LoginRequest {
+    password: String,
+}
+struct LoginHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for LoginHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        let LoginRequest { password } = match serde_json::from_slice::<LoginRequest>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        if !auth::is_paired(&self.storage)? {
+            connection.initiate_response(400, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(b"ERROR: no device password has been set")?;
+            return Ok(());
+        }
+        if !auth::check_password(&self.storage, &password)? {
+            connection.initiate_response(403, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(b"ERROR: incorrect password")?;
+            return Ok(());
+        }
+
+        let token = auth::issue_token(&self.storage, Role::Admin)?;
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(token.as_bytes())?;
+        Ok(())
+    }
+}
+
+struct LogoutHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for LogoutHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if let Some(token) = auth::request_token(connection).map(ToOwned::to_owned) {
+            auth::revoke_token(&self.storage, &token)?;
+        }
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(b"logged out")?;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    role: Role,
+}
+struct TokenHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+impl Handler<EspHttpConnection<'_>> for TokenHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        let TokenRequest { role } = match serde_json::from_slice::<TokenRequest>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        let token = auth::issue_token(&self.storage, role)?;
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(token.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "espidf")]
+struct OtaHandler {
+    storage: Arc<Mutex<StorageController>>,
+    runtime: Arc<Mutex<RuntimeContext>>,
+}
+#[cfg(target_os = "espidf")]
+impl OtaHandler {
+    fn log(&self, msg: &str) {
+        println!("{msg}");
+        let mut runtime = self.runtime.lock().unwrap();
+        runtime.output.push(msg);
+        runtime.output.push("\n");
+    }
+    /// Streams the request body into the inactive ota slot.
+    /// On failure, returns the http status code and reason to report to the client.
+    fn receive(&self, connection: &mut EspHttpConnection<'_>, update: &mut esp_idf_svc::ota::EspOtaUpdate<'_>, total: usize) -> Result<(), (u16, String)> {
+        let mut buf = vec![0u8; OTA_BUFFER_SIZE];
+        let mut received = 0;
+        let mut reported_percent = 0;
+        loop {
+            let len = connection.read(&mut buf).map_err(|e| (500, format!("failed to receive firmware image: {e}")))?;
+            if len == 0 { break }
+
+            if received == 0 && buf[0] != ESP_IMAGE_MAGIC {
+                return Err((400, "not a valid firmware image".into()));
+            }
+            if received + len > total {
+                return Err((400, "firmware image was larger than reported".into()));
+            }
+
+            update.write(&buf[..len]).map_err(|e| (500, format!("failed to write firmware image: {e}")))?;
+            received += len;
+
+            let percent = received * 100 / total;
+            if percent >= reported_percent + 10 {
+                reported_percent = percent - percent % 10;
+                self.log(&format!(">>> firmware update: {reported_percent}% ({received} / {total} bytes)"));
+            }
+        }
+        if received != total {
+            return Err((400, format!("firmware upload was interrupted ({received} / {total} bytes)")));
+        }
+        Ok(())
+    }
+}
+#[cfg(target_os = "espidf")]
+impl Handler<EspHttpConnection<'_>> for OtaHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !auth::is_paired(&self.storage)? {
+            connection.initiate_response(401, None, &[
+                ("Access-Control-Allow-Origin", cors_origin()),
+                ("Content-Type", "text/plain"),
+            ])?;
+            connection.write(b"ERROR: firmware updates require a device password to be set first")?;
+            return Ok(());
+        }
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        let total = match connection.header("Content-Length").and_then(|x| x.parse::<usize>().ok()) {
+            Some(x) if x > 0 => x,
+            _ => {
+                connection.initiate_response(411, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: firmware upload requires a content length")?;
+                return Ok(());
+            }
+        };
+
+        self.log(&format!("\n>>> starting firmware update ({total} bytes)\n"));
+
+        let mut ota = esp_idf_svc::ota::EspOta::new()?;
+        let mut update = ota.initiate_update()?;
+        // on success, complete() validates the image and switches the boot partition to the new slot
+        let res = match self.receive(connection, &mut update, total) {
+            Ok(()) => update.complete().map_err(|e| (400, format!("firmware image failed validation: {e}"))),
+            Err(e) => {
+                let _ = update.abort();
+                Err(e)
+            }
+        };
+
+        match res {
+            Ok(()) => {
+                self.log("\n>>> firmware update complete... rebooting...\n");
+                connection.initiate_response(200, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"successfully updated firmware... rebooting...")?;
+
+                thread::spawn(|| {
+                    thread::sleep(OTA_REBOOT_DELAY); // give the server time to send the response
+                    esp_idf_hal::reset::restart();
+                });
+            }
+            Err((status, msg)) => {
+                self.log(&format!("\n>>> firmware update failed: {msg}\n"));
+                connection.initiate_response(status, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(format!("ERROR: {msg}").as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "espidf")]
+#[derive(Deserialize)]
+struct TlsConfig {
+    cert: Option<String>, // none (along with key) regenerates a self-signed certificate
+    key: Option<String>,
+}
+#[cfg(target_os = "espidf")]
+struct TlsConfigHandler {
+    storage: Arc<Mutex<StorageController>>,
+}
+#[cfg(target_os = "espidf")]
+impl Handler<EspHttpConnection<'_>> for TlsConfigHandler {
+    type Error = EspError;
+    fn handle(&self, connection: &mut EspHttpConnection<'_>) -> Result<(), Self::Error> {
+        if !authorize(&self.storage, connection, Role::Admin)? { return Ok(()) }
+
+        let config = match serde_json::from_slice::<TlsConfig>(&read_all(connection)?) {
+            Ok(x) => x,
+            Err(_) => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: failed to parse request body")?;
+                return Ok(());
+            }
+        };
+
+        let msg: &[u8] = match config {
+            TlsConfig { cert: Some(cert), key: Some(key) } => match cert::validate(&cert, &key) {
+                Ok(()) => {
+                    let mut storage = self.storage.lock().unwrap();
+                    storage.tls_cert().set(&cert)?;
+                    storage.tls_key().set(&key)?;
+                    storage.tls_auto_san().clear()?;
+                    b"successfully uploaded certificate... restart the board to apply changes..."
+                }
+                Err(e) => {
+                    connection.initiate_response(400, None, &[
+                        ("Access-Control-Allow-Origin", cors_origin()),
+                        ("Content-Type", "text/plain"),
+                    ])?;
+                    connection.write(format!("ERROR: invalid certificate or key: {e}").as_bytes())?;
+                    return Ok(());
+                }
+            }
+            TlsConfig { cert: None, key: None } => {
+                let mut storage = self.storage.lock().unwrap();
+                storage.tls_cert().clear()?;
+                storage.tls_key().clear()?;
+                storage.tls_auto_san().clear()?;
+                b"a new self-signed certificate will be generated... restart the board to apply changes..."
+            }
+            _ => {
+                connection.initiate_response(400, None, &[
+                    ("Access-Control-Allow-Origin", cors_origin()),
+                    ("Content-Type", "text/plain"),
+                ])?;
+                connection.write(b"ERROR: certificate and key must be given together")?;
+                return Ok(());
+            }
+        };
+
+        connection.initiate_response(200, None, &[
+            ("Access-Control-Allow-Origin", cors_origin()),
+            ("Content-Type", "text/plain"),
+        ])?;
+        connection.write(msg)?;
+        Ok(())
+    }
+}
+
 enum ServerCommand {
     SetProject(String),
     Input(Input),
+    I2cScan(Sender<Vec<platform::I2cScanResult>>),
+    ReloadPeripherals,
 }
 
 pub struct RuntimeContext {
@@ -427,6 +1117,7 @@ pub struct RuntimeContext {
     output: StringRing,
     errors: StringRing,
     commands: VecDeque<ServerCommand>,
+    connection: Arc<Mutex<ConnectionState>>,
 }
 
 pub struct Executor {
@@ -437,6 +1128,10 @@ pub struct Executor {
 impl Executor {
     pub fn new(event_loop: EspSystemEventLoop, nvs_partition: EspDefaultNvsPartition, modem: WifiModem) -> Result<Self, EspError> {
         let storage = Arc::new(Mutex::new(StorageController::new(EspDefaultNvs::new(nvs_partition.clone(), "nb", true)?)?));
+
+        #[cfg(target_os = "espidf")]
+        ota::check_boot(&storage)?;
+
         let wifi = Arc::new(Mutex::new(Wifi::new(modem, event_loop, nvs_partition, storage.clone())?));
 
         let wifi_connected = {
@@ -447,8 +1142,14 @@ impl Executor {
 
         if wifi_connected {
             // run sntp with immediate correction for one iteration just to get real world time (otherwise we can only measure uptime)
+            // note: the network might not have internet access, so give up after a while rather than hanging forever
             let sntp = EspSntp::new(&SntpConf { sync_mode: SyncMode::Immediate, ..Default::default() })?;
+            let sntp_start = Instant::now();
             while sntp.get_sync_status() != SyncStatus::Completed {
+                if sntp_start.elapsed() > SNTP_TIMEOUT {
+                    println!("failed to sync time -- continuing with uptime only");
+                    break;
+                }
                 thread::sleep(Duration::from_millis(50));
             }
         }
@@ -461,35 +1162,92 @@ impl Executor {
             output, errors,
             running: true,
             commands: Default::default(),
+            connection: Arc::new(Mutex::new(ConnectionState::Offline)),
         }));
 
         Ok(Executor { storage, wifi, runtime })
     }
-    pub fn run(&self, peripherals: platform::SyscallPeripherals) -> ! {
-        let (config, syscalls, peripherals_status_html) = {
-            let mut peripherals_status_html = String::new();
-            let peripherals_config = match self.storage.lock().unwrap().peripherals().get().unwrap() {
-                Some(x) => match netsblox_vm::json::parse_json(&x) {
-                    Ok(x) => x,
-                    Err(e) => {
-                        write!(peripherals_status_html, "<p>failed to parse peripherals config: {e:?}</p>").unwrap();
-                        Default::default()
-                    }
+    /// Marks the running firmware as good so that it is not rolled back after an update (see `ota::confirm_boot`).
+    fn confirm_boot(&self) {
+        #[cfg(target_os = "espidf")]
+        if let Err(e) = ota::confirm_boot(&self.storage) {
+            println!("failed to confirm firmware boot: {e:?}");
+        }
+    }
+    fn ota_status_html(&self) -> String {
+        #[cfg(target_os = "espidf")]
+        {
+            let mut res = String::new();
+            if let Ok(running) = esp_idf_svc::ota::EspOta::new().and_then(|ota| ota.get_running_slot()) {
+                if let Some(firmware) = running.firmware {
+                    write!(res, "<p>Version: {}</p>", firmware.version).unwrap();
                 }
-                None => Default::default(),
-            };
-            let (config, syscalls, init_errors) = platform::bind_syscalls(peripherals, &peripherals_config);
-            match init_errors.is_empty() {
-                true => peripherals_status_html.push_str("<p>successfully loaded peripherals</p>"),
-                false => {
-                    peripherals_status_html.push_str("<p>failed to initialize peripherals:</p>");
-                    for e in init_errors.iter() {
-                        write!(peripherals_status_html, "<p>{} -- {:?}</p>", e.context, e.error).unwrap();
-                    }
+            }
+            if let Some(reason) = self.storage.lock().unwrap().ota_rollback().get().unwrap() {
+                write!(res, "<p>{reason}</p>").unwrap();
+            }
+            res
+        }
+        #[cfg(not(target_os = "espidf"))]
+        "<p>Not supported by the simulator</p>".into()
+    }
+    fn tls_status_html(&self) -> String {
+        #[cfg(target_os = "espidf")]
+        {
+            match self.storage.lock().unwrap().tls_auto_san().get().unwrap() {
+                Some(san) => format!("<p>Self-signed certificate for {san}</p>"),
+                None => "<p>Custom certificate</p>".into(),
+            }
+        }
+        #[cfg(not(target_os = "espidf"))]
+        "<p>Not supported by the simulator (serves plain HTTP)</p>".into()
+    }
+    /// Binds the syscalls for the stored peripherals config, with messages from peripherals sent to `messages`.
+    /// Along with the syscall config and menu, this returns the i2c scanner and a status message (html) for the control panel.
+    fn bind_peripherals(&self, peripherals: platform::SyscallPeripherals, messages: &Sender<IncomingMessage>) -> (Config<platform::C, EspSystem<platform::C>>, Vec<SyscallMenu>, platform::I2cScanner, String) {
+        let mut peripherals_status_html = String::new();
+        let peripherals_config = match self.storage.lock().unwrap().peripherals().get().unwrap() {
+            Some(x) => match netsblox_vm::json::parse_json(&x) {
+                Ok(x) => x,
+                Err(e) => {
+                    write!(peripherals_status_html, "<p>failed to parse peripherals config: {e:?}</p>").unwrap();
+                    Default::default()
                 }
             }
-            (config, syscalls, peripherals_status_html)
+            None => Default::default(),
         };
+        let (config, mut syscalls, init_errors, i2c_scanner) = platform::bind_syscalls(peripherals, &peripherals_config, messages);
+        syscalls.push(SyscallMenu::Submenu { label: "Network".into(), content: vec![
+            SyscallMenu::Entry { label: "getState".into(), value: "Network.getState".into() },
+        ] });
+        match init_errors.is_empty() {
+            true => peripherals_status_html.push_str("<p>successfully loaded peripherals</p>"),
+            false => {
+                peripherals_status_html.push_str("<p>failed to initialize peripherals:</p>");
+                for e in init_errors.iter() {
+                    write!(peripherals_status_html, "<p>{} -- {:?}</p>", e.context, e.error).unwrap();
+                }
+            }
+        }
+        (config, syscalls, i2c_scanner, peripherals_status_html)
+    }
+    pub fn run(&self, peripherals: platform::SyscallPeripherals) -> ! {
+        let (local_message_sender, local_message_receiver) = channel();
+        let (config, syscalls, mut i2c_scanner, peripherals_status_html) = self.bind_peripherals(peripherals, &local_message_sender);
+
+        let (cloud_url, editor_url, services_url) = {
+            let mut storage = self.storage.lock().unwrap();
+            let cloud_url = storage.cloud_url().get().unwrap();
+            let editor_url = storage.editor_url().get().unwrap();
+            let services_url = storage.services_url().get().unwrap();
+            (cloud_url, editor_url, services_url)
+        };
+        let client_id = get_client_id(&self.storage).unwrap();
+        let url_status_html = format!("<p>Client ID: {client_id}</p><p>Cloud: {}</p><p>Editor: {}</p><p>Services: {}</p>",
+            cloud_url.as_deref().unwrap_or(DEFAULT_CLOUD_URL), editor_url.as_deref().unwrap_or(DEFAULT_EDITOR_URL), services_url.as_deref().unwrap_or("default"));
+        let cloud_url = cloud_url.unwrap_or_else(|| DEFAULT_CLOUD_URL.into());
+        let editor_url = editor_url.unwrap_or_else(|| DEFAULT_EDITOR_URL.into());
+        CORS_ORIGIN.set(url_origin(&editor_url).into()).unwrap();
 
         let (ap_ip, client_ip) = {
             let wifi = self.wifi.lock().unwrap();
@@ -498,17 +1256,28 @@ impl Executor {
             println!("wifi server ip: {ap_ip:?}");
             (ap_ip, client_ip)
         };
+        if !auth::is_paired(&self.storage).unwrap() {
+            println!("device pairing code: {}", auth::pairing_code());
+        }
 
-        macro_rules! parse_x509 {
-            ($loc:literal) => {
-                X509::pem_until_nul(concat_bytes!(include_bytes!($loc), b"\0"))
+        #[cfg(target_os = "espidf")]
+        let server_config = {
+            let ips = [Some(ap_ip), client_ip].into_iter().flatten().collect::<Vec<_>>();
+            let cert::Certificate { cert, key } = cert::get_or_generate(&self.storage, &ips).unwrap();
+
+            // the server needs these for the rest of the program, so just leak them
+            let cert: &'static str = Box::leak(format!("{cert}\0").into_boxed_str());
+            let key: &'static str = Box::leak(format!("{key}\0").into_boxed_str());
+            Configuration {
+                server_certificate: Some(X509::pem_until_nul(cert.as_bytes())),
+                private_key: Some(X509::pem_until_nul(key.as_bytes())),
+                ..Default::default()
             }
-        }
-        let mut server = EspHttpServer::new(&Configuration {
-            server_certificate: Some(parse_x509!("../cacert.pem")),
-            private_key: Some(parse_x509!("../privkey.pem")),
-            ..Default::default()
-        }).unwrap();
+        };
+        #[cfg(not(target_os = "espidf"))]
+        let server_config = Configuration::default();
+
+        let mut server = EspHttpServer::new(&server_config).unwrap();
 
         macro_rules! server_handler {
             ($uri:literal : $($method:path => $handler:expr),*$(,)?) => {{
@@ -517,46 +1286,75 @@ impl Executor {
             }}
         }
 
-        let root_content = include_str!("www/index.html")
-            .replace("%%%AP_INFO%%%", &format!("<p>IP: {ap_ip}</p>"))
+        let root_template = include_str!("www/index.html")
+            .replace("%%%AP_INFO%%%", &match client_ip {
+                Some(_) => format!("<p>IP: {ap_ip}</p>"),
+                None => format!("<p>IP: {ap_ip}</p><p>Running in offline mode</p><p><a class='editor-link' target='_blank' href='{editor_url}?extensions=[\"{}/extension.js\"]'>Open Editor</a></p>", server_url(ap_ip)),
+            })
             .replace("%%%CLIENT_INFO%%%", &match client_ip {
-                Some(client_ip) => format!("<p>IP: {client_ip}</p><p><a target='_blank' href='{EDITOR_URL}?extensions=[\"https://{client_ip}/extension.js\"]'>Open Editor</a></p>"),
+                Some(client_ip) => format!("<p>IP: {client_ip}</p><p><a class='editor-link' target='_blank' href='{editor_url}?extensions=[\"{}/extension.js\"]'>Open Editor</a></p>", server_url(client_ip)),
                 None => "<p>Not Connected</p>".into(),
             })
-            .replace("%%%PERIPH_INFO%%%", &peripherals_status_html);
+            .replace("%%%URL_INFO%%%", &url_status_html)
+            .replace("%%%AUTH_INFO%%%", match auth::is_paired(&self.storage).unwrap() {
+                true => "<p>This device is password protected</p>",
+                false => "<p>This device has not been paired... set a device password below, using the pairing code printed to the serial console at boot as the current password</p>",
+            })
+            .replace("%%%OTA_INFO%%%", &self.ota_status_html())
+            .replace("%%%TLS_INFO%%%", &self.tls_status_html());
+        let root_content = Arc::new(Mutex::new(root_template.replace("%%%PERIPH_INFO%%%", &peripherals_status_html)));
         drop(peripherals_status_html);
+        drop(url_status_html);
 
-        server_handler!("/": Method::Get => RootHandler { content: root_content });
+        server_handler!("/": Method::Get => RootHandler { content: root_content.clone() });
         server_handler!("/wipe": Method::Post => WipeHandler { storage: self.storage.clone() });
         server_handler!("/wifi": Method::Post => WifiConfigHandler { storage: self.storage.clone() });
-
-        // if we're not connected to the internet, just host the board config server and do nothing else
-        let client_ip = client_ip.unwrap_or_else(|| loop {
-            thread::sleep(Duration::from_secs(1));
-        });
-
-        let extension = ExtensionArgs {
-            server: &format!("https://{client_ip}"),
-            syscalls: &syscalls,
-            omitted_elements: &["thumbnail", "pentrails", "history", "replay"],
-            pull_interval: Duration::from_millis(500),
-        }.render();
+        server_handler!("/password": Method::Post => PasswordConfigHandler { storage: self.storage.clone() });
+        server_handler!("/login": Method::Post => LoginHandler { storage: self.storage.clone() });
+        server_handler!("/logout": Method::Post => LogoutHandler { storage: self.storage.clone() });
+        server_handler!("/tokens": Method::Post => TokenHandler { storage: self.storage.clone() });
+        server_handler!("/urls": Method::Post => UrlConfigHandler { storage: self.storage.clone() });
+        server_handler!("/client-id": Method::Post => ClientIdConfigHandler { storage: self.storage.clone() });
+        #[cfg(target_os = "espidf")]
+        server_handler!("/ota": Method::Post => OtaHandler { storage: self.storage.clone(), runtime: self.runtime.clone() });
+        #[cfg(target_os = "espidf")]
+        server_handler!("/tls": Method::Post => TlsConfigHandler { storage: self.storage.clone() });
+
+        // if we're not connected to a wifi network, serve everything from the access point and run in offline mode
+        let server_ip = client_ip.unwrap_or(ap_ip);
+
+        let server = server_url(server_ip);
+        let render_extension = |syscalls: &[SyscallMenu]| {
+            let extension = ExtensionArgs {
+                server: &server,
+                syscalls,
+                omitted_elements: &["thumbnail", "pentrails", "history", "replay"],
+                pull_interval: Duration::from_millis(500),
+            }.render();
+            // attach the auth token (if any) to every request the extension makes
+            let extension = inject_before(extension, "const SERVER = ", &format!("const TOKEN = '{EXTENSION_TOKEN_PLACEHOLDER}';\n    "));
+            inject_before(extension, "req.send(info.body);", "if (TOKEN) req.setRequestHeader('Authorization', `Bearer ${TOKEN}`);\n        ")
+        };
+        let extension = Arc::new(Mutex::new(render_extension(&syscalls)));
         drop(syscalls);
 
-        server_handler!("/extension.js": Method::Get => ExtensionHandler { extension });
-        server_handler!("/pull": Method::Post => PullStatusHandler { runtime: self.runtime.clone() });
-        server_handler!("/input": Method::Post => InputHandler { runtime: self.runtime.clone() });
-        server_handler!("/toggle-paused": Method::Post => TogglePausedHandler { runtime: self.runtime.clone() });
+        server_handler!("/extension.js": Method::Get => ExtensionHandler { storage: self.storage.clone(), extension: extension.clone() });
+        server_handler!("/pull": Method::Post => PullStatusHandler { storage: self.storage.clone(), runtime: self.runtime.clone() });
+        server_handler!("/input": Method::Post => InputHandler { storage: self.storage.clone(), runtime: self.runtime.clone() });
+        server_handler!("/toggle-paused": Method::Post => TogglePausedHandler { storage: self.storage.clone(), runtime: self.runtime.clone() });
+        server_handler!("/i2c-scan": Method::Get => I2cScanHandler { storage: self.storage.clone(), runtime: self.runtime.clone() });
         server_handler!("/project":
             Method::Get => GetProjectHandler { storage: self.storage.clone() },
-            Method::Post => SetProjectHandler { runtime: self.runtime.clone() },
+            Method::Post => SetProjectHandler { storage: self.storage.clone(), runtime: self.runtime.clone() },
         );
         server_handler!("/peripherals":
             Method::Get => GetPeripheralsHandler { storage: self.storage.clone() },
-            Method::Post => SetPeripheralsHandler { storage: self.storage.clone() },
+            Method::Post => SetPeripheralsHandler { storage: self.storage.clone(), runtime: self.runtime.clone() },
         );
+        server_handler!("/peripherals/validate": Method::Post => ValidatePeripheralsHandler { storage: self.storage.clone() });
+        server_handler!("/peripherals/schema": Method::Get => PeripheralsSchemaHandler);
 
-        println!("running: {EDITOR_URL}?extensions=[\"https://{client_ip}/extension.js\"]");
+        println!("running: {editor_url}?extensions=[\"{server}/extension.js\"]");
 
         macro_rules! tee_println {
             ($runtime:expr => $($t:tt)*) => {{
@@ -568,6 +1366,31 @@ impl Executor {
             }}
         }
 
+        // syscalls go through this so that they can be swapped out when the peripherals are reloaded
+        let syscall_config = Rc::new(RefCell::new(config));
+        let config = Config::<platform::C, EspSystem<platform::C>> {
+            request: {
+                let syscall_config = syscall_config.clone();
+                Some(Rc::new(move |mc, key, request, proc| {
+                    let handler = syscall_config.borrow().request.clone();
+                    match handler {
+                        Some(handler) => handler(mc, key, request, proc),
+                        None => RequestStatus::UseDefault { key, request },
+                    }
+                }))
+            },
+            command: {
+                let syscall_config = syscall_config.clone();
+                Some(Rc::new(move |mc, key, command, proc| {
+                    let handler = syscall_config.borrow().command.clone();
+                    match handler {
+                        Some(handler) => handler(mc, key, command, proc),
+                        None => CommandStatus::UseDefault { key, command },
+                    }
+                }))
+            },
+        };
+
         let runtime = self.runtime.clone();
         let config = config.fallback(&Config {
             command: Some(Rc::new(move |_, key, command, proc| match command {
@@ -586,7 +1409,7 @@ impl Executor {
 
         let clock = Arc::new(Clock::new(UtcOffset::UTC, None));
 
-        let system = Rc::new(EspSystem::<platform::C>::new(CLOUD_URL.into(), Some("project".into()), config, clock));
+        let system = Rc::new(EspSystem::<platform::C>::new(client_ip.map(|_| cloud_url.into()), services_url.map(Into::into), client_id.into(), Some("project".into()), config, clock, self.runtime.lock().unwrap().connection.clone(), local_message_receiver));
 
         let mut running_env = {
             let role = {
@@ -599,11 +1422,16 @@ impl Executor {
             running_env.proj.borrow_mut(mc).input(&mc, Input::Start);
         });
 
-        tee_println!(&mut *self.runtime.lock().unwrap() => "\n>>> starting project (public id: {})\n", system.get_public_id());
+        match system.is_online() {
+            true => tee_println!(&mut *self.runtime.lock().unwrap() => "\n>>> starting project (public id: {})\n", system.get_public_id()),
+            false => tee_println!(&mut *self.runtime.lock().unwrap() => "\n>>> starting project (offline mode)\n"),
+        }
 
         let mut idle_sleeper = IdleAction::new(YIELDS_BEFORE_IDLE_SLEEP, Box::new(|| thread::sleep(IDLE_SLEEP_TIME)));
         let mut steps_since_gc = 0;
 
+        self.confirm_boot();
+
         loop {
             let command = self.runtime.lock().unwrap().commands.pop_front();
             match command {
@@ -630,6 +1458,34 @@ impl Executor {
                         running_env.proj.borrow_mut(mc).input(&mc, x);
                     });
                 }
+                Some(ServerCommand::I2cScan(sender)) => {
+                    let _ = sender.send(i2c_scanner.scan()); // the handler may have timed out
+                }
+                Some(ServerCommand::ReloadPeripherals) => {
+                    // a paused project stays paused (and is not restarted) after the reload
+                    let was_running = self.runtime.lock().unwrap().running;
+                    running_env.mutate(|mc, running_env| {
+                        running_env.proj.borrow_mut(mc).input(&mc, Input::Stop);
+                    });
+
+                    // drop the old drivers (and anything else holding onto them) so the peripherals can be taken back
+                    *syscall_config.borrow_mut() = Config { request: None, command: None };
+                    i2c_scanner = Default::default();
+                    let peripherals = unsafe { platform::SyscallPeripherals::reclaim() };
+
+                    let (config, syscalls, new_i2c_scanner, peripherals_status_html) = self.bind_peripherals(peripherals, &local_message_sender);
+                    *syscall_config.borrow_mut() = config;
+                    i2c_scanner = new_i2c_scanner;
+                    *extension.lock().unwrap() = render_extension(&syscalls);
+                    *root_content.lock().unwrap() = root_template.replace("%%%PERIPH_INFO%%%", &peripherals_status_html);
+
+                    if was_running {
+                        running_env.mutate(|mc, running_env| {
+                            running_env.proj.borrow_mut(mc).input(&mc, Input::Start);
+                        });
+                    }
+                    tee_println!(&mut *self.runtime.lock().unwrap() => "\n>>> reloaded peripherals\n");
+                }
                 None => (),
             }
 
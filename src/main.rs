@@ -1,18 +1,26 @@
+#[cfg(target_os = "espidf")]
 use esp_idf_sys as _; // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 
 use netsblox_vm_esp32::Executor;
 use netsblox_vm_esp32::platform::SyscallPeripherals;
 
-use esp_idf_svc::nvs::EspDefaultNvsPartition;
-use esp_idf_svc::eventloop::EspSystemEventLoop;
+#[cfg(target_os = "espidf")] use {esp_idf_sys as sys, esp_idf_hal as hal, esp_idf_svc as svc};
+#[cfg(not(target_os = "espidf"))] use netsblox_vm_esp32::sim::{self, sys, hal, svc};
 
-use esp_idf_hal::peripherals::Peripherals;
-use esp_idf_hal::modem::WifiModem;
+use svc::nvs::EspDefaultNvsPartition;
+use svc::eventloop::EspSystemEventLoop;
+
+use hal::peripherals::Peripherals;
+use hal::modem::WifiModem;
 
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
-    esp_idf_sys::link_patches();
+    sys::link_patches();
+
+    // when running in the simulator, allow driving simulated inputs from stdin
+    #[cfg(not(target_os = "espidf"))]
+    sim::spawn_console();
 
     let (exe, peripherals) = {
         let event_loop = EspSystemEventLoop::take().unwrap();
@@ -26,7 +34,11 @@ fn main() {
         let peripherals = SyscallPeripherals {
             pins: peripherals.pins,
             ledc: peripherals.ledc,
-            i2c: peripherals.i2c0,
+            rmt: peripherals.rmt,
+            adc1: peripherals.adc1,
+            i2c0: peripherals.i2c0,
+            i2c1: peripherals.i2c1,
+            spi: peripherals.spi2,
         };
 
         (exe, peripherals)
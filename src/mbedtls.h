@@ -0,0 +1,5 @@
+// extra esp-idf-sys bindings (available as esp_idf_sys::mbedtls) used to generate and validate the https server certificate
+
+#include "mbedtls/pk.h"
+#include "mbedtls/ecp.h"
+#include "mbedtls/x509_crt.h"
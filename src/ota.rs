@@ -0,0 +1,93 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::thread;
+
+use esp_idf_svc::ota::EspOta;
+use esp_idf_sys::{self as sys, EspError};
+
+use embedded_svc::ota::{Slot, SlotState};
+
+use crate::storage::StorageController;
+
+// max time a freshly installed firmware has to finish booting before it is rolled back
+const BOOT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(180);
+
+static BOOT_CONFIRMED: AtomicBool = AtomicBool::new(false);
+
+fn describe_slot(slot: &Slot) -> String {
+    match &slot.firmware {
+        Some(firmware) => format!("{} ({})", firmware.version, slot.label),
+        None => slot.label.to_string(),
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn describe_reset_reason() -> &'static str {
+    match unsafe { sys::esp_reset_reason() } {
+        sys::esp_reset_reason_t_ESP_RST_POWERON => "lost power",
+        sys::esp_reset_reason_t_ESP_RST_PANIC => "crashed",
+        sys::esp_reset_reason_t_ESP_RST_BROWNOUT => "browned out",
+        sys::esp_reset_reason_t_ESP_RST_INT_WDT | sys::esp_reset_reason_t_ESP_RST_TASK_WDT | sys::esp_reset_reason_t_ESP_RST_WDT => "triggered a watchdog",
+        sys::esp_reset_reason_t_ESP_RST_SW => "restarted",
+        _ => "was reset",
+    }
+}
+
+/// Checks the boot state of the running firmware.
+///
+/// If this is the first boot after an update, the firmware is on probation until [`confirm_boot`] is called.
+/// If it resets before then (or takes too long to get there), the bootloader rolls back to the previous firmware,
+/// which will then detect the failed update and record the reason in [`StorageController::ota_rollback`].
+pub fn check_boot(storage: &Arc<Mutex<StorageController>>) -> Result<(), EspError> {
+    let running = EspOta::new()?.get_running_slot()?;
+    let mut storage_lock = storage.lock().unwrap();
+
+    if matches!(running.state, SlotState::Unverified) {
+        let firmware = describe_slot(&running);
+        println!("verifying updated firmware {firmware}");
+        storage_lock.ota_pending().set(&firmware)?;
+        storage_lock.ota_rollback().clear()?;
+        drop(storage_lock);
+
+        let storage = storage.clone();
+        thread::spawn(move || {
+            thread::sleep(BOOT_CONFIRM_TIMEOUT);
+            if BOOT_CONFIRMED.load(Ordering::SeqCst) { return }
+
+            let reason = format!("firmware update to {firmware} was rolled back: it did not finish booting within {} seconds", BOOT_CONFIRM_TIMEOUT.as_secs());
+            println!("{reason}");
+            let _ = storage.lock().unwrap().ota_rollback().set(&reason);
+            match EspOta::new() {
+                Ok(mut ota) => println!("failed to roll back firmware: {:?}", ota.mark_running_slot_invalid_and_reboot()),
+                Err(e) => println!("failed to roll back firmware: {e:?}"),
+            }
+        });
+    } else if let Some(firmware) = storage_lock.ota_pending().get()? {
+        // the last boot was an unconfirmed update, but we're not it, so the bootloader must have rolled it back
+        storage_lock.ota_pending().clear()?;
+        if storage_lock.ota_rollback().get()?.is_none() {
+            let reason = format!("firmware update to {firmware} was rolled back: it {} before finishing booting", describe_reset_reason());
+            println!("{reason}");
+            storage_lock.ota_rollback().set(&reason)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Marks the running firmware as valid if it is still on probation after an update.
+/// This should be called once the firmware is up and running (i.e., serving http and executing the vm).
+pub fn confirm_boot(storage: &Arc<Mutex<StorageController>>) -> Result<(), EspError> {
+    if BOOT_CONFIRMED.swap(true, Ordering::SeqCst) { return Ok(()) }
+
+    let mut ota = EspOta::new()?;
+    let running = ota.get_running_slot()?;
+    if matches!(running.state, SlotState::Unverified) {
+        ota.mark_running_slot_valid()?;
+        storage.lock().unwrap().ota_pending().clear()?;
+        println!("confirmed updated firmware {}", describe_slot(&running));
+    }
+
+    Ok(())
+}
@@ -1,27 +1,62 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::time::{Instant, Duration};
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::thread;
 use std::rc::Rc;
 use std::iter;
+use std::ops::RangeInclusive;
+use std::num::NonZeroU32;
+use std::fs;
+use std::io::Write as _;
+use std::path::PathBuf;
 
-use netsblox_vm::runtime::{EntityKind, GetType, System, Value, ProcessKind, Config, Request, RequestStatus, SimpleValue, Number};
+use netsblox_vm::runtime::{EntityKind, GetType, System, Value, ProcessKind, Config, Request, RequestStatus, Command, CommandStatus, SimpleValue, Number, Note, IncomingMessage};
 use netsblox_vm::gc::gc_arena;
 use netsblox_vm::runtime::{CustomTypes, Key, Unwindable};
 use netsblox_vm::template::SyscallMenu;
-use netsblox_vm::compact_str::format_compact;
-
-use esp_idf_sys::EspError;
-
-use esp_idf_hal::units::FromValueType;
-use esp_idf_hal::ledc::{config::TimerConfig, LEDC, Resolution, LedcTimerDriver, LedcDriver};
-use esp_idf_hal::gpio::{Pins, PinDriver, AnyInputPin, AnyOutputPin, AnyIOPin, Input, Output, Level};
-use esp_idf_hal::delay::Ets;
-use esp_idf_hal::i2c::{I2cDriver, I2cError, I2C0};
-
-use embedded_hal::i2c::{I2c, AddressMode as I2cAddressMode};
-
-use serde::Deserialize;
+use netsblox_vm::std_util::AsyncKey;
+use netsblox_vm::compact_str::{CompactString, format_compact};
+
+use crate::sys::{self, EspError, adc_atten_t};
+
+use crate::hal::units::FromValueType;
+#[cfg(target_os = "espidf")] use crate::hal::peripheral::Peripheral;
+use crate::hal::ledc::{config::TimerConfig, LEDC, Resolution, LedcTimerDriver, LedcDriver};
+use crate::hal::gpio::{Pins, PinDriver, AnyInputPin, AnyOutputPin, AnyIOPin, Input, Output, InterruptType, Pull};
+use crate::hal::gpio::{Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Gpio9, Gpio10};
+use crate::hal::rmt::{self, RMT, TxRmtDriver, Pulse, VariableLengthSignal, config::TransmitConfig};
+use crate::hal::adc::{ADC1, ADCPin, attenuation};
+use crate::hal::adc::oneshot::{AdcDriver, AdcChannelDriver};
+use crate::hal::adc::oneshot::config::{AdcChannelConfig, Calibration};
+use crate::hal::delay::{Ets, TickType};
+use crate::hal::task::notification::Notification;
+use crate::hal::peripherals::Peripherals;
+use crate::hal::i2c::{I2cDriver, I2cError, I2C0, I2C1, config::Config as I2cConfig};
+use crate::hal::spi::{SpiDriver, SpiDeviceDriver, SPI2, config::{DriverConfig as SpiDriverConfig, Config as SpiConfig, Dma}};
+
+use crate::svc::sd::{SdCardDriver, SdCardConfiguration, spi::SdSpiHostDriver};
+use crate::svc::fs::fatfs::Fatfs;
+use crate::svc::io::vfs::MountedFatfs;
+
+use embedded_hal::i2c::{I2c, AddressMode as I2cAddressMode, Operation as I2cOperation};
+use embedded_hal::digital::{InputPin, OutputPin, PinState};
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal::delay::DelayNs;
+use embedded_hal::spi::SpiDevice;
+
+use embedded_graphics::{Pixel, Drawable};
+use embedded_graphics::geometry::{Point, Size, OriginDimensions};
+use embedded_graphics::draw_target::DrawTarget;
+use embedded_graphics::pixelcolor::{BinaryColor, Rgb565, IntoStorage};
+use embedded_graphics::primitives::{Primitive, Line, Rectangle, PrimitiveStyle};
+use embedded_graphics::mono_font::{MonoTextStyle, ascii::{FONT_6X10, FONT_10X20}};
+use embedded_graphics::text::{Text, Baseline};
+
+use serde::{Serialize, Deserialize};
+use schemars::JsonSchema;
 
 use crate::system::EspSystem;
 
@@ -29,49 +64,131 @@ use crate::system::EspSystem;
 
 type PinNumber = u8;
 
+const MOTOR_PWM_FREQUENCY_KHZ: u32 = 20;
+const SERVO_PWM_FREQUENCY_HZ: u32 = 50;
+const LEDC_CLOCK_HZ: u64 = 80_000_000; // apb clock, which bounds frequency * 2^resolution_bits
+const LEDC_RESOLUTION_BITS: RangeInclusive<u8> = 1..=14;
+const BUZZER_BASE_FREQUENCY_HZ: u32 = 1000;
+const BUZZER_RESOLUTION_BITS: u8 = 12; // low enough for ~20 kHz tones, high enough for the clock divider to reach ~20 Hz
+const BUZZER_SOUND_SECS_PER_BEAT: f64 = 1.0; // the tempo property is not supported, so use the default of 60 bpm
+const RMT_CLOCK_DIVIDER: u8 = 2; // 40 MHz ticks, plenty for ws2812 timings
+const MAX_NEOPIXEL_COUNT: u16 = 300; // the whole strip is encoded into a single rmt signal, which takes 96 bytes per pixel
+const MAX_DEBOUNCE_MS: u32 = 1000;
+const EDGE_POLL_INTERVAL_MS: u64 = 100; // how often edge watchers check if they should stop
+const IS31FL3741_WIDTH: u8 = 13;
+const IS31FL3741_HEIGHT: u8 = 9;
+const IS31FL3741_PWM_PAGES: [usize; 2] = [0xb4, 0xab]; // number of pwm registers on each pwm page
+const OLED_WIDTH: usize = 128;
+const DEFAULT_I2C_BUS: &str = "default";
+const I2C_SCAN_ADDRESSES: RangeInclusive<u8> = 0x08..=0x77; // everything else is reserved by the i2c spec
+const SPI_DMA_BUFFER_SIZE: usize = 4096; // the sd card driver needs dma for its 512-byte blocks
+const ST7789_SPI_FREQUENCY_HZ: u32 = 40_000_000;
+const ST7789_MAX_SIZE: [u16; 2] = [240, 320]; // size of the display ram
+const ST7789_FLUSH_CHUNK_BYTES: usize = SPI_DMA_BUFFER_SIZE; // one dma transfer per chunk
+const SD_CARD_MAX_OPEN_FILES: usize = 4;
+
+type InputPinDriver = PinDriver<'static, AnyInputPin, Input>;
+type OutputPinDriver = PinDriver<'static, AnyOutputPin, Output>;
+type DigitalInPinDriver = PinDriver<'static, AnyIOPin, Input>; // pulls are only available on pins that can also be outputs
+type DigitalOutPinDriver = PinDriver<'static, AnyIOPin, Output>; // likewise for open drain, which needs the input buffer
+type SharedI2cDriver = SharedI2c<I2cDriver<'static>>;
+type SharedSpiDriver = Rc<SpiDriver<'static>>; // each device on the bus has its own chip select via SpiDeviceDriver
+type SharedSpiDevice = SpiDeviceDriver<'static, SharedSpiDriver>;
+type SdCardMount = MountedFatfs<Fatfs<SdCardDriver<SdSpiHostDriver<'static, SharedSpiDriver>>>>;
+type CommandKey = AsyncKey<Result<(), CompactString>>;
+
 struct PeripheralHandles {
-    digital_ins: BTreeMap<String, DigitalInController>,
-    digital_outs: BTreeMap<String, DigitalOutController>,
+    i2c_buses: BTreeMap<String, SharedI2cDriver>,
+
+    digital_ins: BTreeMap<String, DigitalInController<SharedPin<DigitalInPinDriver>>>,
+    digital_outs: BTreeMap<String, DigitalOutController<DigitalOutPinDriver>>,
+    analog_ins: BTreeMap<String, AnalogInController>,
+
+    motor_groups: BTreeMap<String, Vec<Rc<RefCell<MotorController<LedcDriver<'static>>>>>>, // they say to use ledc driver for general purpose pwm: https://esp-rs.github.io/esp-idf-hal/esp_idf_hal/ledc/index.html
+    servos: BTreeMap<String, ServoController<LedcDriver<'static>>>,
+    pwm_outs: BTreeMap<String, PwmOutController<LedcDriver<'static>>>,
+    buzzers: BTreeMap<String, BuzzerController>,
+    pwms: PwmManager, // kept alive so that the ledc timers are not reset
+
+    neopixels: BTreeMap<String, NeoPixelController<TxRmtDriver<'static>>>,
 
-    motor_groups: BTreeMap<String, Vec<Rc<RefCell<MotorController>>>>,
+    hcsr04s: BTreeMap<String, HCSR04Controller<OutputPinDriver, InputPinDriver, Ets>>,
 
-    hcsr04s: BTreeMap<String, HCSR04Controller>,
+    max30205s: BTreeMap<String, max30205::MAX30205<SharedI2cDriver>>,
+    is31fl3741s: BTreeMap<String, IS31FL3741Controller<SharedI2cDriver>>,
+    ssd1306s: BTreeMap<String, OledController<SharedI2cDriver>>,
+    bmp388s: BTreeMap<String, bmp388::BMP388<SharedI2cDriver>>,
+    lis3dhs: BTreeMap<String, lis3dh::Lis3dh<lis3dh::Lis3dhI2C<SharedI2cDriver>>>,
+    veml7700s: BTreeMap<String, veml6030::Veml6030<SharedI2cDriver>>,
 
-    max30205s: BTreeMap<String, max30205::MAX30205<SharedI2c<I2cDriver<'static>>>>,
-    is31fl3741s: BTreeMap<String, is31fl3741::devices::AdafruitRGB13x9<SharedI2c<I2cDriver<'static>>>>,
-    bmp388s: BTreeMap<String, bmp388::BMP388<SharedI2c<I2cDriver<'static>>>>,
-    lis3dhs: BTreeMap<String, lis3dh::Lis3dh<lis3dh::Lis3dhI2C<SharedI2c<I2cDriver<'static>>>>>,
-    veml7700s: BTreeMap<String, veml6030::Veml6030<SharedI2c<I2cDriver<'static>>>>,
+    st7789s: BTreeMap<String, St7789Controller<SharedSpiDevice, OutputPinDriver>>,
+    sd_cards: BTreeMap<String, SdCardController>,
 }
 
-#[derive(Default, Debug, Deserialize)]
+/// The peripherals config, which is uploaded as JSON through the board configuration page (see `peripherals.md`).
+#[derive(Default, Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct PeripheralsConfig {
+    /// The default I2C bus, which is named `"default"` and used by devices that do not give an `i2c_bus`.
     #[serde(default)] i2c: Option<I2cInfo>,
+    /// Additional named I2C buses.
+    #[serde(default)] i2c_buses: Vec<I2cInfo>,
+    #[serde(default)] spi: Option<SpiInfo>,
 
-    #[serde(default)] digital_ins: Vec<DigitalIO>,
-    #[serde(default)] digital_outs: Vec<DigitalIO>,
+    #[serde(default)] digital_ins: Vec<DigitalIn>,
+    #[serde(default)] digital_outs: Vec<DigitalOut>,
+    #[serde(default)] analog_ins: Vec<AnalogIn>,
 
     #[serde(default)] motors: Vec<Motor>,
     #[serde(default)] motor_groups: Vec<MotorGroup>,
 
+    #[serde(default)] servos: Vec<Servo>,
+    #[serde(default)] pwm_outs: Vec<PwmOut>,
+    #[serde(default)] buzzers: Vec<Buzzer>,
+
+    #[serde(default)] neopixels: Vec<NeoPixel>,
+
     #[serde(default)] hcsr04s: Vec<HCSR04>,
 
     #[serde(default)] max30205s: Vec<BasicI2c>,
     #[serde(default)] is31fl3741s: Vec<BasicI2c>,
+    #[serde(default)] ssd1306s: Vec<Ssd1306>,
     #[serde(default)] bmp388s: Vec<BasicI2c>,
     #[serde(default)] lis3dhs: Vec<BasicI2c>,
     #[serde(default)] veml7700s: Vec<BasicI2c>,
+
+    #[serde(default)] st7789s: Vec<St7789>,
+    #[serde(default)] sd_cards: Vec<SdCard>,
 }
 
-#[derive(Debug, Deserialize)]
+/// An I2C bus. At most two buses can be configured, since the board only has two I2C controllers.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct I2cInfo {
+    #[serde(default = "I2cInfo::default_name")] name: String,
     gpio_sda: PinNumber,
     gpio_scl: PinNumber,
+    /// The bus clock in Hz.
+    #[serde(default = "I2cInfo::default_frequency")] frequency: u32,
+    #[serde(default = "I2cInfo::default_pullups")] pullups: bool,
+}
+impl I2cInfo {
+    fn default_name() -> String { DEFAULT_I2C_BUS.into() }
+    fn default_frequency() -> u32 { 1_000_000 }
+    fn default_pullups() -> bool { true }
 }
 
-#[derive(Debug, Deserialize)]
+/// The SPI bus, which is shared by every SPI device (each with its own chip select pin).
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SpiInfo {
+    gpio_sclk: PinNumber,
+    gpio_mosi: PinNumber,
+    #[serde(default)] gpio_miso: Option<PinNumber>,
+}
+
+/// A DC motor driven by two PWM pins, one for each direction.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct Motor {
     name: String,
@@ -79,14 +196,171 @@ struct Motor {
     gpio_neg: PinNumber,
 }
 
-#[derive(Debug, Deserialize)]
+/// A named group of motors which can be set with a single syscall.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct MotorGroup {
     name: String,
     motors: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// A hobby servo driven by a 50 Hz PWM signal, whose pulse width sets the angle.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Servo {
+    name: String,
+    gpio: PinNumber,
+    /// The pulse width (in microseconds) for `min_angle`.
+    #[serde(default = "Servo::default_min_pulse_us")] min_pulse_us: u32,
+    /// The pulse width (in microseconds) for `max_angle`.
+    #[serde(default = "Servo::default_max_pulse_us")] max_pulse_us: u32,
+    #[serde(default = "Servo::default_min_angle")] min_angle: f64,
+    #[serde(default = "Servo::default_max_angle")] max_angle: f64,
+}
+impl Servo {
+    fn default_min_pulse_us() -> u32 { 1000 }
+    fn default_max_pulse_us() -> u32 { 2000 }
+    fn default_min_angle() -> f64 { 0.0 }
+    fn default_max_angle() -> f64 { 180.0 }
+
+    fn check(&self) -> Result<(), PeripheralError> {
+        let period_us = 1_000_000 / SERVO_PWM_FREQUENCY_HZ;
+        if self.min_pulse_us >= self.max_pulse_us || self.max_pulse_us >= period_us {
+            return Err(PeripheralError::Other { cause: format!("min_pulse_us must be less than max_pulse_us, which must be less than the {period_us} us period") });
+        }
+        if !self.min_angle.is_finite() || !self.max_angle.is_finite() || self.min_angle == self.max_angle {
+            return Err(PeripheralError::Other { cause: "min_angle and max_angle must be distinct numbers".into() });
+        }
+        Ok(())
+    }
+}
+
+/// A generic PWM output with an adjustable duty cycle and frequency.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct PwmOut {
+    name: String,
+    gpio: PinNumber,
+    /// The initial frequency in Hz.
+    #[serde(default = "PwmOut::default_frequency")] frequency: u32,
+    /// The number of bits of duty cycle resolution (1 to 14).
+    #[schemars(range(min = 1, max = 14))]
+    #[serde(default = "PwmOut::default_resolution_bits")] resolution_bits: u8,
+}
+impl PwmOut {
+    fn default_frequency() -> u32 { 1000 }
+    fn default_resolution_bits() -> u8 { 10 }
+}
+
+/// A passive buzzer or speaker, which plays tones as a square wave.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Buzzer {
+    name: String,
+    gpio: PinNumber,
+}
+
+/// The order in which a NeoPixel strip expects color components.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum ColorOrder {
+    Rgb, #[default] Grb, Rbg, Gbr, Brg, Bgr,
+}
+impl ColorOrder {
+    fn arrange(self, [r, g, b]: [u8; 3]) -> [u8; 3] {
+        match self {
+            ColorOrder::Rgb => [r, g, b],
+            ColorOrder::Grb => [g, r, b],
+            ColorOrder::Rbg => [r, b, g],
+            ColorOrder::Gbr => [g, b, r],
+            ColorOrder::Brg => [b, r, g],
+            ColorOrder::Bgr => [b, g, r],
+        }
+    }
+}
+
+/// A strip of WS2812 (or compatible) addressable RGB leds.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct NeoPixel {
+    name: String,
+    gpio: PinNumber,
+    count: u16,
+    #[serde(default)] color_order: ColorOrder,
+}
+impl NeoPixel {
+    fn check(&self) -> Result<(), PeripheralError> {
+        if !(1..=MAX_NEOPIXEL_COUNT).contains(&self.count) {
+            return Err(PeripheralError::Other { cause: format!("count must be between 1 and {MAX_NEOPIXEL_COUNT}") });
+        }
+        Ok(())
+    }
+}
+
+/// The controller chip of a monochrome OLED display.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum OledDriver {
+    #[default] Ssd1306, Sh1106,
+}
+
+/// A monochrome 128x64 or 128x32 OLED display on an I2C bus.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct Ssd1306 {
+    name: String,
+    i2c_addr: u8,
+    #[serde(default = "I2cInfo::default_name")] i2c_bus: String,
+    #[serde(default)] driver: OledDriver,
+    #[serde(default = "Ssd1306::default_height")] height: u8,
+}
+impl Ssd1306 {
+    fn default_height() -> u8 { 64 }
+
+    fn check(&self) -> Result<(), PeripheralError> {
+        if self.height != 32 && self.height != 64 {
+            return Err(PeripheralError::Other { cause: "height must be 32 or 64".into() });
+        }
+        Ok(())
+    }
+}
+
+/// A color TFT display on the SPI bus.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct St7789 {
+    name: String,
+    gpio_cs: PinNumber,
+    gpio_dc: PinNumber,
+    #[serde(default)] gpio_rst: Option<PinNumber>,
+    #[serde(default = "St7789::default_width")] width: u16,
+    #[serde(default = "St7789::default_height")] height: u16,
+    #[serde(default)] x_offset: u16,
+    #[serde(default)] y_offset: u16,
+}
+impl St7789 {
+    fn default_width() -> u16 { 240 }
+    fn default_height() -> u16 { 320 }
+
+    fn check(&self) -> Result<(), PeripheralError> {
+        let [max_width, max_height] = ST7789_MAX_SIZE;
+        if self.width == 0 || self.height == 0 || self.x_offset as u32 + self.width as u32 > max_width as u32 || self.y_offset as u32 + self.height as u32 > max_height as u32 {
+            return Err(PeripheralError::Other { cause: format!("the display (plus offset) must fit within {max_width}x{max_height}") });
+        }
+        Ok(())
+    }
+}
+
+/// A FAT32-formatted SD card on the SPI bus.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct SdCard {
+    name: String,
+    gpio_cs: PinNumber,
+}
+
+/// An HC-SR04 ultrasonic distance sensor.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct HCSR04 {
     name: String,
@@ -94,38 +368,181 @@ struct HCSR04 {
     gpio_echo: PinNumber,
 }
 
-#[derive(Debug, Deserialize)]
+/// The changes in value of a digital input which send a message.
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Edge {
+    Rising, Falling, Both,
+}
+impl Edge {
+    fn matches(self, value: bool) -> bool {
+        match self {
+            Edge::Rising => value,
+            Edge::Falling => !value,
+            Edge::Both => true,
+        }
+    }
+}
+
+/// The internal pull resistor of a digital input, which sets its value when nothing is driving the pin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum PullMode {
+    #[default] None, Up, Down,
+}
+impl PullMode {
+    fn to_pull(self) -> Pull {
+        match self {
+            PullMode::None => Pull::Floating,
+            PullMode::Up => Pull::Up,
+            PullMode::Down => Pull::Down,
+        }
+    }
+}
+
+/// How a digital output drives its pin.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum DriveMode {
+    #[default] #[serde(alias = "pushPull")] PushPull,
+    #[serde(alias = "openDrain")] OpenDrain,
+}
+
+/// A digital input on a single pin, which can optionally send a message whenever its value changes.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct DigitalIn {
+    name: String,
+    gpio: PinNumber,
+    /// Flips the logic level, so that `true` is low voltage.
+    negated: bool,
+    #[serde(default)] pull: PullMode,
+    /// Sends a message on these changes in value (after `negated` is applied).
+    #[serde(default)] edge: Option<Edge>,
+    /// How long (in milliseconds) the value must be stable before a change is reported.
+    #[serde(default = "DigitalIn::default_debounce_ms")] debounce_ms: u32,
+    /// The type of message that is sent, which defaults to the name of the input.
+    #[serde(default)] msg_type: Option<String>,
+}
+impl DigitalIn {
+    fn default_debounce_ms() -> u32 { 20 }
+
+    fn check(&self) -> Result<(), PeripheralError> {
+        if self.pull != PullMode::None && !AnyPin(self.gpio).supports_pull_and_open_drain() {
+            return Err(PeripheralError::PinInsufficientCapability { pin: self.gpio });
+        }
+        if self.debounce_ms > MAX_DEBOUNCE_MS {
+            return Err(PeripheralError::Other { cause: format!("debounce_ms must be at most {MAX_DEBOUNCE_MS}") });
+        }
+        if self.msg_type.as_deref() == Some("") {
+            return Err(PeripheralError::Other { cause: "msg_type must not be empty".into() });
+        }
+        Ok(())
+    }
+}
+
+/// A digital output on a single pin.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
-struct DigitalIO {
+struct DigitalOut {
     name: String,
     gpio: PinNumber,
+    /// Flips the logic level, so that `true` is low voltage.
     negated: bool,
+    #[serde(default)] drive: DriveMode,
+}
+impl DigitalOut {
+    fn check(&self) -> Result<(), PeripheralError> {
+        if self.drive == DriveMode::OpenDrain && !AnyPin(self.gpio).supports_pull_and_open_drain() {
+            return Err(PeripheralError::PinInsufficientCapability { pin: self.gpio });
+        }
+        Ok(())
+    }
+}
+
+/// The input attenuation of an analog input, which sets the range of voltages that can be measured.
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+enum Attenuation {
+    Db0, Db2_5, Db6, #[default] Db11,
+}
+impl Attenuation {
+    fn to_adc(self) -> adc_atten_t {
+        match self {
+            Attenuation::Db0 => attenuation::NONE,
+            Attenuation::Db2_5 => attenuation::DB_2_5,
+            Attenuation::Db6 => attenuation::DB_6,
+            Attenuation::Db11 => attenuation::DB_11,
+        }
+    }
+}
+
+/// An analog input on one of gpio 1-10, which measures the voltage on the pin.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+struct AnalogIn {
+    name: String,
+    gpio: PinNumber,
+    #[serde(default)] attenuation: Attenuation,
+    #[serde(default = "AnalogIn::default_samples")] samples: u16,
+}
+impl AnalogIn {
+    fn default_samples() -> u16 { 8 }
+
+    fn check(&self) -> Result<(), PeripheralError> {
+        if !(1..=256).contains(&self.samples) {
+            return Err(PeripheralError::Other { cause: "samples must be between 1 and 256".into() });
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, Deserialize)]
+/// An I2C device with no extra options.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 struct BasicI2c {
     name: String,
     i2c_addr: u8,
+    #[serde(default = "I2cInfo::default_name")] i2c_bus: String,
 }
 
 // -----------------------------------------------------------------
 
 #[derive(PartialOrd, Ord, PartialEq, Eq)]
 struct AnyPin(PinNumber);
+struct AnalogPin(PinNumber);
 impl AnyPin {
+    fn try_into_analog(self) -> Option<AnalogPin> {
+        // on the esp32s3, adc1 is connected to gpio 1-10 (adc2 is not usable while wifi is running)
+        matches!(self.0, 1..=10).then_some(AnalogPin(self.0))
+    }
+    // the check_* functions don't touch the hardware, so they can also be used for dry runs.
+    // the esp32s3 has gpio 0-21 and 26-48, and (unlike the esp32) none of them are input only.
+    fn check_input_output(self) -> Option<AnyPin> {
+        matches!(self.0, 0..=21 | 26..=48).then_some(self)
+    }
+    fn check_input(self) -> Option<AnyPin> {
+        self.check_input_output()
+    }
+    fn check_output(self) -> Option<AnyPin> {
+        self.check_input_output()
+    }
+    fn supports_pull_and_open_drain(&self) -> bool {
+        // both need the input and output buffers of the pin
+        AnyPin(self.0).check_input_output().is_some()
+    }
     fn try_into_input_output(self) -> Option<AnyIOPin> {
-        let mut pin = unsafe { AnyIOPin::new(self.0 as i32) };
+        let mut pin = unsafe { AnyIOPin::new(self.check_input_output()?.0 as i32) };
         PinDriver::input_output(&mut pin).ok()?;
         Some(pin)
     }
     fn try_into_input(self) -> Option<AnyInputPin> {
-        let mut pin = unsafe { AnyInputPin::new(self.0 as i32) };
+        let mut pin = unsafe { AnyInputPin::new(self.check_input()?.0 as i32) };
         PinDriver::input(&mut pin).ok()?;
         Some(pin)
     }
     fn try_into_output(self) -> Option<AnyOutputPin> {
-        let mut pin = unsafe { AnyOutputPin::new(self.0 as i32) };
+        let mut pin = unsafe { AnyOutputPin::new(self.check_output()?.0 as i32) };
         PinDriver::output(&mut pin).ok()?;
         Some(pin)
     }
@@ -141,7 +558,13 @@ pub enum PeripheralError {
     NameUnknown { name: String },
     NameAlreadyTaken { name: String },
     PwmOutOfChannels,
+    PwmOutOfTimers,
+    PwmFrequencyUnsupported { frequency: u32, resolution_bits: u8 },
+    PwmResolutionUnsupported { resolution_bits: u8 },
+    RmtOutOfChannels,
+    I2cOutOfControllers,
     I2cNotConfigured,
+    SpiNotConfigured,
     EspError(EspError),
     I2cError(I2cError),
     Other { cause: String },
@@ -154,43 +577,113 @@ struct GpioManager {
 }
 impl GpioManager {
     fn new(_: Pins) -> Self { // take ownership of all pins for safety reasons
+        Self::new_dry_run()
+    }
+    /// Creates a manager which only keeps track of which pins are taken, for validating configs without touching the hardware.
+    /// Only [`GpioManager::take`] may be used, unless the conversion function is also hardware-free (e.g., [`AnyPin::try_into_analog`]).
+    fn new_dry_run() -> Self {
         Self { pins: (0..=48).map(|x| (x, Some(()))).collect() }
     }
-    fn take_convert<T>(&mut self, id: PinNumber, f: fn(AnyPin) -> Option<T>) -> Result<T, PeripheralError> {
+    fn take(&mut self, id: PinNumber) -> Result<(), PeripheralError> {
         match self.pins.get_mut(&id) {
             Some(x) => match x.take() {
-                Some(()) => match f(AnyPin(id)) {
-                    Some(x) => Ok(x),
-                    None => Err(PeripheralError::PinInsufficientCapability { pin: id }),
-                }
+                Some(()) => Ok(()),
                 None => Err(PeripheralError::PinAlreadyTaken { pin: id }),
             }
             None => Err(PeripheralError::PinUnknown { pin: id }),
         }
     }
+    fn take_convert<T>(&mut self, id: PinNumber, f: fn(AnyPin) -> Option<T>) -> Result<T, PeripheralError> {
+        self.take(id)?;
+        f(AnyPin(id)).ok_or(PeripheralError::PinInsufficientCapability { pin: id })
+    }
 }
 
+/// Identifies which outputs can share an ledc timer (and therefore its frequency and resolution).
+/// Motors and servos never share a timer with generic pwm outputs, since the frequency of the latter can be changed at runtime.
+/// Buzzers never share a timer at all, since each one plays its own tones.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct PwmTimerConfig {
+    usage: PwmUsage,
+    frequency: u32,
+    resolution_bits: u8,
+}
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PwmUsage {
+    Motor, Servo, Output, Buzzer,
+}
+impl PwmTimerConfig {
+    fn motor() -> Self {
+        Self { usage: PwmUsage::Motor, frequency: MOTOR_PWM_FREQUENCY_KHZ * 1000, resolution_bits: 10 }
+    }
+    fn servo() -> Self {
+        // high resolution for precise pulse widths
+        Self { usage: PwmUsage::Servo, frequency: SERVO_PWM_FREQUENCY_HZ, resolution_bits: 14 }
+    }
+    fn buzzer() -> Self {
+        Self { usage: PwmUsage::Buzzer, frequency: BUZZER_BASE_FREQUENCY_HZ, resolution_bits: BUZZER_RESOLUTION_BITS }
+    }
+    fn resolution(&self) -> Option<Resolution> {
+        Some(match self.resolution_bits {
+            1 => Resolution::Bits1, 2 => Resolution::Bits2, 3 => Resolution::Bits3, 4 => Resolution::Bits4, 5 => Resolution::Bits5,
+            6 => Resolution::Bits6, 7 => Resolution::Bits7, 8 => Resolution::Bits8, 9 => Resolution::Bits9, 10 => Resolution::Bits10,
+            11 => Resolution::Bits11, 12 => Resolution::Bits12, 13 => Resolution::Bits13, 14 => Resolution::Bits14,
+            _ => return None,
+        })
+    }
+}
+
+/// Changes the frequency of a running ledc timer without checking that it is valid for the timer's resolution.
+fn set_timer_frequency(timer: u8, frequency: u32) -> Result<(), PeripheralError> {
+    match EspError::from(unsafe { sys::ledc_set_freq(sys::ledc_mode_t_LEDC_LOW_SPEED_MODE, timer as sys::ledc_timer_t, frequency) }) {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the ledc clock can generate the given frequency at the given resolution (which must itself be supported).
+fn check_pwm_frequency(frequency: u32, resolution_bits: u8) -> Result<(), PeripheralError> {
+    if !LEDC_RESOLUTION_BITS.contains(&resolution_bits) {
+        return Err(PeripheralError::PwmResolutionUnsupported { resolution_bits });
+    }
+    if frequency == 0 || (frequency as u64) << resolution_bits > LEDC_CLOCK_HZ {
+        return Err(PeripheralError::PwmFrequencyUnsupported { frequency, resolution_bits });
+    }
+    Ok(())
+}
+
+struct PwmTimer<T: 'static> {
+    peripheral: Option<T>,
+    driver: Option<(Arc<LedcTimerDriver<'static, T>>, PwmTimerConfig)>,
+}
+impl<T> PwmTimer<T> {
+    fn new(peripheral: T) -> Self {
+        Self { peripheral: Some(peripheral), driver: None }
+    }
+    fn config(&self) -> Option<PwmTimerConfig> {
+        self.driver.as_ref().map(|(_, x)| *x)
+    }
+}
+
+/// Hands out ledc channels, each of which is attached to a timer with the requested config.
+/// Timers are created on demand and shared between all channels with the same config.
 struct PwmManager {
-    channel0: Option<esp_idf_hal::ledc::CHANNEL0>,
-    channel1: Option<esp_idf_hal::ledc::CHANNEL1>,
-    channel2: Option<esp_idf_hal::ledc::CHANNEL2>,
-    channel3: Option<esp_idf_hal::ledc::CHANNEL3>,
-    channel4: Option<esp_idf_hal::ledc::CHANNEL4>,
-    channel5: Option<esp_idf_hal::ledc::CHANNEL5>,
-    channel6: Option<esp_idf_hal::ledc::CHANNEL6>,
-    channel7: Option<esp_idf_hal::ledc::CHANNEL7>,
-    timer: Arc<LedcTimerDriver<'static, esp_idf_hal::ledc::TIMER0>>,
+    channel0: Option<crate::hal::ledc::CHANNEL0>,
+    channel1: Option<crate::hal::ledc::CHANNEL1>,
+    channel2: Option<crate::hal::ledc::CHANNEL2>,
+    channel3: Option<crate::hal::ledc::CHANNEL3>,
+    channel4: Option<crate::hal::ledc::CHANNEL4>,
+    channel5: Option<crate::hal::ledc::CHANNEL5>,
+    channel6: Option<crate::hal::ledc::CHANNEL6>,
+    channel7: Option<crate::hal::ledc::CHANNEL7>,
+    timer0: PwmTimer<crate::hal::ledc::TIMER0>,
+    timer1: PwmTimer<crate::hal::ledc::TIMER1>,
+    timer2: PwmTimer<crate::hal::ledc::TIMER2>,
+    timer3: PwmTimer<crate::hal::ledc::TIMER3>,
 }
 impl PwmManager {
-    fn new(ledc: LEDC) -> Result<Self, EspError> {
-        let timer_config = TimerConfig {
-            frequency: 20.kHz().into(),
-            resolution: Resolution::Bits10,
-        };
-        let timer = Arc::new(LedcTimerDriver::new(ledc.timer0, &timer_config)?);
-
-        Ok(Self {
-            timer,
+    fn new(ledc: LEDC) -> Self {
+        Self {
             channel0: Some(ledc.channel0),
             channel1: Some(ledc.channel1),
             channel2: Some(ledc.channel2),
@@ -199,18 +692,200 @@ impl PwmManager {
             channel5: Some(ledc.channel5),
             channel6: Some(ledc.channel6),
             channel7: Some(ledc.channel7),
-        })
+            timer0: PwmTimer::new(ledc.timer0),
+            timer1: PwmTimer::new(ledc.timer1),
+            timer2: PwmTimer::new(ledc.timer2),
+            timer3: PwmTimer::new(ledc.timer3),
+        }
+    }
+    /// Gets the index of a timer with the given config, creating one if needed (always the case for buzzers).
+    fn get_timer(&mut self, config: PwmTimerConfig) -> Result<u8, PeripheralError> {
+        check_pwm_frequency(config.frequency, config.resolution_bits)?;
+        let timer_config = TimerConfig {
+            frequency: config.frequency.Hz().into(),
+            resolution: config.resolution().ok_or(PeripheralError::PwmResolutionUnsupported { resolution_bits: config.resolution_bits })?,
+        };
+        macro_rules! find_or_create {
+            ($($index:literal => $timer:ident),+) => {
+                $(if config.usage != PwmUsage::Buzzer && self.$timer.driver.as_ref().is_some_and(|(_, x)| *x == config) {
+                    return Ok($index);
+                })+
+                $(if let Some(timer) = self.$timer.peripheral.as_mut() {
+                    // only give up the peripheral once the driver exists, so that a failure doesn't lose the timer
+                    let driver = LedcTimerDriver::new(unsafe { timer.clone_unchecked() }, &timer_config)?;
+                    self.$timer.peripheral = None;
+                    self.$timer.driver = Some((Arc::new(driver), config));
+                    return Ok($index);
+                })+
+            }
+        }
+        find_or_create! { 0 => timer0, 1 => timer1, 2 => timer2, 3 => timer3 }
+        Err(PeripheralError::PwmOutOfTimers)
+    }
+    /// Attaches the pin to a free channel using a timer with the given config.
+    /// On success, also returns the index of the timer that was used.
+    fn take(&mut self, pin: AnyOutputPin, config: PwmTimerConfig) -> Result<(LedcDriver<'static>, u8), PeripheralError> {
+        let has_channel = self.channel0.is_some() || self.channel1.is_some() || self.channel2.is_some() || self.channel3.is_some()
+            || self.channel4.is_some() || self.channel5.is_some() || self.channel6.is_some() || self.channel7.is_some();
+        if !has_channel {
+            return Err(PeripheralError::PwmOutOfChannels);
+        }
+
+        let timer = self.get_timer(config)?;
+        macro_rules! try_in_order {
+            ($driver:ident => $($name:ident),+) => {$(
+                if let Some(channel) = self.$name.take() {
+                    return Ok((LedcDriver::new(channel, $driver, pin)?, timer));
+                }
+            )+}
+        }
+        macro_rules! with_timer {
+            ($($index:literal => $timer:ident),+) => {
+                match timer {
+                    $($index => {
+                        let driver = self.$timer.driver.as_ref().unwrap().0.clone();
+                        try_in_order! { driver => channel0, channel1, channel2, channel3, channel4, channel5, channel6, channel7 }
+                    })+
+                    _ => unreachable!(),
+                }
+            }
+        }
+        with_timer! { 0 => timer0, 1 => timer1, 2 => timer2, 3 => timer3 }
+        Err(PeripheralError::PwmOutOfChannels)
+    }
+    /// Changes the frequency of a timer (and therefore every channel attached to it).
+    fn set_frequency(&self, timer: u8, frequency: u32) -> Result<(), PeripheralError> {
+        let config = match timer {
+            0 => self.timer0.config(),
+            1 => self.timer1.config(),
+            2 => self.timer2.config(),
+            3 => self.timer3.config(),
+            _ => None,
+        }.ok_or(PeripheralError::Other { cause: format!("ledc timer {timer} is not in use") })?;
+        check_pwm_frequency(frequency, config.resolution_bits)?;
+        set_timer_frequency(timer, frequency)
+    }
+}
+
+/// Keeps track of ledc channel and timer usage the same way as [`PwmManager`], but without any hardware (for validating configs).
+struct PwmBudget {
+    channels: usize,
+    timers: Vec<PwmTimerConfig>,
+}
+impl PwmBudget {
+    fn new() -> Self {
+        Self { channels: 8, timers: Vec::with_capacity(4) }
     }
-    fn take(&mut self, pin: AnyOutputPin) -> Result<LedcDriver<'static>, PeripheralError> {
+    fn take(&mut self, config: PwmTimerConfig) -> Result<(), PeripheralError> {
+        if self.channels == 0 {
+            return Err(PeripheralError::PwmOutOfChannels);
+        }
+        check_pwm_frequency(config.frequency, config.resolution_bits)?;
+        if config.usage == PwmUsage::Buzzer || !self.timers.contains(&config) {
+            if self.timers.len() >= 4 {
+                return Err(PeripheralError::PwmOutOfTimers);
+            }
+            self.timers.push(config);
+        }
+        self.channels -= 1;
+        Ok(())
+    }
+}
+
+/// Hands out rmt channels for transmitting signals.
+struct RmtManager {
+    channel0: Option<rmt::CHANNEL0>,
+    channel1: Option<rmt::CHANNEL1>,
+    channel2: Option<rmt::CHANNEL2>,
+    channel3: Option<rmt::CHANNEL3>,
+}
+impl RmtManager {
+    fn new(rmt: RMT) -> Self {
+        Self {
+            channel0: Some(rmt.channel0),
+            channel1: Some(rmt.channel1),
+            channel2: Some(rmt.channel2),
+            channel3: Some(rmt.channel3),
+        }
+    }
+    fn take(&mut self, pin: AnyOutputPin) -> Result<TxRmtDriver<'static>, PeripheralError> {
+        let config = TransmitConfig::new().clock_divider(RMT_CLOCK_DIVIDER);
         macro_rules! try_in_order {
             ($($name:ident),+) => {$(
                 if let Some(channel) = self.$name.take() {
-                    return Ok(LedcDriver::new(channel, self.timer.clone(), pin)?);
+                    return Ok(TxRmtDriver::new(channel, pin, &config)?);
                 }
             )+}
         }
-        try_in_order! { channel0, channel1, channel2, channel3, channel4, channel5, channel6, channel7 }
-        Err(PeripheralError::PwmOutOfChannels)
+        try_in_order! { channel0, channel1, channel2, channel3 }
+        Err(PeripheralError::RmtOutOfChannels)
+    }
+}
+
+struct I2cManager {
+    i2c0: Option<I2C0>,
+    i2c1: Option<I2C1>,
+}
+impl I2cManager {
+    fn new(i2c0: I2C0, i2c1: I2C1) -> Self {
+        Self { i2c0: Some(i2c0), i2c1: Some(i2c1) }
+    }
+    fn take(&mut self, sda: AnyIOPin, scl: AnyIOPin, config: &I2cConfig) -> Result<I2cDriver<'static>, PeripheralError> {
+        macro_rules! try_in_order {
+            ($($name:ident),+) => {$(
+                if let Some(i2c) = self.$name.take() {
+                    return Ok(I2cDriver::new(i2c, sda, scl, config)?);
+                }
+            )+}
+        }
+        try_in_order! { i2c0, i2c1 }
+        Err(PeripheralError::I2cOutOfControllers)
+    }
+}
+
+/// Gets a handle to the named i2c bus (the unnamed `i2c` config entry is the default bus).
+fn get_i2c_bus(buses: &BTreeMap<String, SharedI2cDriver>, name: &str) -> Result<SharedI2cDriver, PeripheralError> {
+    match buses.get(name) {
+        Some(x) => Ok(x.clone()),
+        None if name == DEFAULT_I2C_BUS => Err(PeripheralError::I2cNotConfigured),
+        None => Err(PeripheralError::NameUnknown { name: name.into() }),
+    }
+}
+
+trait AnalogChannel {
+    fn read_raw(&mut self) -> Result<u16, EspError>;
+    fn read_mv(&mut self) -> Result<u16, EspError>;
+}
+impl<T: ADCPin<Adc = ADC1>> AnalogChannel for AdcChannelDriver<'static, T, Rc<AdcDriver<'static, ADC1>>> {
+    fn read_raw(&mut self) -> Result<u16, EspError> {
+        AdcChannelDriver::read_raw(self)
+    }
+    fn read_mv(&mut self) -> Result<u16, EspError> {
+        AdcChannelDriver::read(self) // calibrated, so this is in mV
+    }
+}
+
+struct AdcManager {
+    adc1: Rc<AdcDriver<'static, ADC1>>,
+}
+impl AdcManager {
+    fn new(adc1: ADC1) -> Result<Self, EspError> {
+        Ok(Self { adc1: Rc::new(AdcDriver::new(adc1)?) })
+    }
+    fn take(&mut self, pin: AnalogPin, attenuation: Attenuation) -> Result<Box<dyn AnalogChannel>, PeripheralError> {
+        // uses the efuse calibration values to convert readings to voltages
+        let config = AdcChannelConfig { attenuation: attenuation.to_adc(), calibration: Calibration::Curve, ..Default::default() };
+
+        // adc channel drivers need the concrete pin types, but we've already checked that the pin is free and adc capable
+        macro_rules! match_pins {
+            ($($num:literal => $gpio:ident),+$(,)?) => {
+                match pin.0 {
+                    $($num => Ok(Box::new(AdcChannelDriver::new(self.adc1.clone(), unsafe { $gpio::new() }, &config)?)),)+
+                    _ => Err(PeripheralError::PinInsufficientCapability { pin: pin.0 }),
+                }
+            }
+        }
+        match_pins! { 1 => Gpio1, 2 => Gpio2, 3 => Gpio3, 4 => Gpio4, 5 => Gpio5, 6 => Gpio6, 7 => Gpio7, 8 => Gpio8, 9 => Gpio9, 10 => Gpio10 }
     }
 }
 
@@ -277,7 +952,7 @@ impl<T: embedded_hal::i2c::ErrorType> embedded_hal::i2c::ErrorType for SharedI2c
     type Error = T::Error;
 }
 impl<T: I2c<A>, A: I2cAddressMode> I2c<A> for SharedI2c<T> {
-    fn transaction(&mut self, address: A, operations: &mut [esp_idf_hal::i2c::Operation<'_>]) -> Result<(), Self::Error> {
+    fn transaction(&mut self, address: A, operations: &mut [I2cOperation<'_>]) -> Result<(), Self::Error> {
         self.0.borrow_mut().transaction(address, operations)
     }
     fn read(&mut self, address: A, buffer: &mut [u8]) -> Result<(), Self::Error> {
@@ -291,126 +966,910 @@ impl<T: I2c<A>, A: I2cAddressMode> I2c<A> for SharedI2c<T> {
     }
 }
 
+/// A device type that can be found by an i2c scan, optionally confirmed by reading an id register.
+struct KnownI2cDevice {
+    name: &'static str,
+    addresses: RangeInclusive<u8>,
+    id: Option<(u8, u8)>, // (register, expected value)
+}
+const KNOWN_I2C_DEVICES: &[KnownI2cDevice] = &[
+    KnownI2cDevice { name: "VEML7700", addresses: 0x10..=0x10, id: Some((0x07, 0x81)) }, // low byte of the device id
+    KnownI2cDevice { name: "LIS3DH", addresses: 0x18..=0x19, id: Some((0x0f, 0x33)) },
+    KnownI2cDevice { name: "IS31FL3741", addresses: 0x30..=0x33, id: None },
+    KnownI2cDevice { name: "SSD1306", addresses: 0x3c..=0x3d, id: None },
+    KnownI2cDevice { name: "MAX30205", addresses: 0x40..=0x5f, id: None },
+    KnownI2cDevice { name: "BMP388", addresses: 0x76..=0x77, id: Some((0x00, 0x50)) },
+];
+
+#[derive(Debug, Serialize)]
+pub struct I2cScanResult {
+    pub bus: String,
+    pub address: u8,
+    pub devices: Vec<&'static str>,
+}
+
+/// Finds every address on an i2c bus which acknowledges an empty write, along with the device types it might be.
+fn scan_i2c_bus<I: I2c>(i2c: &mut I) -> Vec<(u8, Vec<&'static str>)> {
+    let mut res = vec![];
+    for address in I2C_SCAN_ADDRESSES {
+        if i2c.write(address, &[]).is_err() { continue }
+        let devices = KNOWN_I2C_DEVICES.iter().filter(|device| device.addresses.contains(&address)).filter(|device| match device.id {
+            Some((register, expected)) => {
+                let mut value = [0];
+                i2c.write_read(address, &[register], &mut value).is_ok() && value[0] == expected
+            }
+            None => true,
+        }).map(|device| device.name).collect();
+        res.push((address, devices));
+    }
+    res
+}
+
+/// Scans the configured i2c buses on demand (e.g., for the `/i2c-scan` endpoint).
+/// This is not [`Send`], so it must be used from the thread that runs the vm.
+#[derive(Default)]
+pub struct I2cScanner {
+    buses: BTreeMap<String, SharedI2cDriver>,
+}
+impl I2cScanner {
+    pub fn scan(&self) -> Vec<I2cScanResult> {
+        let mut res = vec![];
+        for (bus, i2c) in self.buses.iter() {
+            for (address, devices) in scan_i2c_bus(&mut i2c.clone()) {
+                res.push(I2cScanResult { bus: bus.clone(), address, devices });
+            }
+        }
+        res
+    }
+}
+
 // -----------------------------------------------------------------
 
-fn measure_pulse(pin: &mut PinDriver<'static, AnyInputPin, Input>, level: Level, timeout: Duration) -> Option<Duration> {
+fn measure_pulse<P: InputPin>(pin: &mut P, level: PinState, timeout: Duration) -> Result<Option<Duration>, P::Error> {
+    let level = level == PinState::High;
     let total_start = Instant::now();
-    while pin.get_level() != level {
+    while pin.is_high()? != level {
         if total_start.elapsed() > timeout {
-            return None;
+            return Ok(None);
         }
     }
     let pulse_start = Instant::now();
-    while pin.get_level() == level {
+    while pin.is_high()? == level {
         if total_start.elapsed() > timeout {
-            return None;
+            return Ok(None);
         }
     }
-    Some(pulse_start.elapsed())
+    Ok(Some(pulse_start.elapsed()))
 }
 
-struct MotorController {
-    positive: LedcDriver<'static>, // they say to use ledc driver for general purpose pwm: https://esp-rs.github.io/esp-idf-hal/esp_idf_hal/ledc/index.html
-    negative: LedcDriver<'static>,
+struct MotorController<P: SetDutyCycle> {
+    positive: P,
+    negative: P,
 }
-impl MotorController {
-    fn set_power(&mut self, power: f64) -> Result<(), EspError> {
+impl<P: SetDutyCycle> MotorController<P> {
+    fn set_power(&mut self, power: f64) -> Result<(), P::Error> {
         let max_input = 255;
-        let max_duty = self.positive.get_max_duty() as i32;
+        let max_duty = self.positive.max_duty_cycle() as i32;
         let duty = (power as i32).clamp(-max_input, max_input) * max_duty / max_input;
 
         if duty >= 0 {
-            self.negative.set_duty(0)?;
-            self.positive.set_duty(duty as u32)?;
+            self.negative.set_duty_cycle(0)?;
+            self.positive.set_duty_cycle(duty as u16)?;
         } else {
-            self.positive.set_duty(0)?;
-            self.negative.set_duty((-duty) as u32)?;
+            self.positive.set_duty_cycle(0)?;
+            self.negative.set_duty_cycle((-duty) as u16)?;
         }
 
         Ok(())
     }
 }
 
-struct DigitalInController {
-    pin: PinDriver<'static, AnyInputPin, Input>,
-    negated: bool,
+struct ServoController<P: SetDutyCycle> {
+    pwm: P,
+    min_pulse_us: u32,
+    max_pulse_us: u32,
+    min_angle: f64,
+    max_angle: f64,
 }
-impl DigitalInController {
-    fn get_value(&self) -> bool {
-        self.pin.is_high() ^ self.negated
+impl<P: SetDutyCycle> ServoController<P> {
+    fn set_angle(&mut self, angle: f64) -> Result<(), P::Error> {
+        let (lo, hi) = (self.min_angle.min(self.max_angle), self.min_angle.max(self.max_angle));
+        let frac = (angle.clamp(lo, hi) - self.min_angle) / (self.max_angle - self.min_angle);
+        let pulse_us = self.min_pulse_us as f64 + frac * (self.max_pulse_us - self.min_pulse_us) as f64;
+
+        let period_us = 1_000_000.0 / SERVO_PWM_FREQUENCY_HZ as f64;
+        let duty = (pulse_us / period_us * self.pwm.max_duty_cycle() as f64).round();
+        self.pwm.set_duty_cycle(duty as u16)
+    }
+    fn release(&mut self) -> Result<(), P::Error> {
+        self.pwm.set_duty_cycle(0) // no pulses means the servo stops holding its position
     }
 }
 
-struct DigitalOutController {
-    pin: PinDriver<'static, AnyOutputPin, Output>,
-    negated: bool,
+struct PwmOutController<P: SetDutyCycle> {
+    pwm: P,
+    timer: u8,
 }
-impl DigitalOutController {
-    fn set_value(&mut self, value: bool) -> Result<(), EspError> {
-        self.pin.set_level(if value ^ self.negated { Level::High } else { Level::Low })
+impl<P: SetDutyCycle> PwmOutController<P> {
+    fn set_duty(&mut self, percent: f64) -> Result<(), P::Error> {
+        let duty = (percent.clamp(0.0, 100.0) / 100.0 * self.pwm.max_duty_cycle() as f64).round();
+        self.pwm.set_duty_cycle(duty as u16)
     }
 }
 
-struct HCSR04Controller {
-    trigger: PinDriver<'static, AnyOutputPin, Output>,
-    echo: PinDriver<'static, AnyInputPin, Input>,
+/// A single tone played by a buzzer, where a frequency of zero is a rest.
+#[derive(Clone, Copy, Debug)]
+struct Tone {
+    frequency: f64,
+    duration: Duration,
 }
-impl HCSR04Controller {
-    fn get_distance(&mut self) -> Result<f64, EspError> {
-        self.trigger.set_high()?;
-        Ets::delay_us(10);
-        self.trigger.set_low()?;
-        let duration = measure_pulse(&mut self.echo, Level::High, Duration::from_millis(50)).map(|x| x.as_micros()).unwrap_or(0);
-        Ok(duration as f64 * 0.01715) // half (because round trip) the speed of sound in cm/us
+impl Tone {
+    /// Creates a tone with the given frequency (in Hz) and duration (in seconds), or `None` if the duration is too long to represent (e.g., infinite).
+    /// Negative values are treated as zero.
+    fn new(frequency: f64, secs: f64) -> Option<Self> {
+        Some(Self { frequency: frequency.max(0.0), duration: Duration::try_from_secs_f64(secs.max(0.0)).ok()? })
+    }
+    /// Parses a `[note, duration]` pair, where the note is a frequency (in Hz), a note name like `"C4"` or `"F#5"`, or `"rest"`,
+    /// and the duration is in milliseconds.
+    fn parse<C: CustomTypes<S>, S: System<C>>(value: &Value<'_, C, S>) -> Option<Self> {
+        let pair = value.as_list().ok()?;
+        let pair = pair.borrow();
+        if pair.len() != 2 {
+            return None;
+        }
+        let frequency = match pair[0].as_number() {
+            Ok(x) => x.get(),
+            Err(_) => match pair[0].as_string().ok()?.trim() {
+                x if x.is_empty() || x.eq_ignore_ascii_case("rest") => 0.0,
+                x => Note::from_name(x)?.get_frequency().get(),
+            }
+        };
+        let duration = pair[1].as_number().ok()?.get();
+        Self::new(frequency, duration / 1000.0)
     }
 }
 
-// -----------------------------------------------------------------
-
-pub struct SyscallPeripherals {
-    pub pins: Pins,
-    pub ledc: LEDC,
-    pub i2c: I2C0,
+struct BuzzerPlayback {
+    tones: Vec<Tone>,
+    done: Option<CommandKey>,
 }
 
-pub struct InitError {
-    pub context: String,
-    pub error: PeripheralError,
+/// A pin which is shared between threads, e.g., between a [`DigitalInController`] and its [`EdgeWatcher`].
+struct SharedPin<T>(Arc<Mutex<T>>);
+impl<T> SharedPin<T> {
+    fn new(pin: T) -> Self {
+        Self(Arc::new(Mutex::new(pin)))
+    }
+}
+impl<T> Clone for SharedPin<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+impl<T: embedded_hal::digital::ErrorType> embedded_hal::digital::ErrorType for SharedPin<T> {
+    type Error = T::Error;
+}
+impl<T: InputPin> InputPin for SharedPin<T> {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        self.0.lock().unwrap().is_high()
+    }
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        self.0.lock().unwrap().is_low()
+    }
 }
 
-pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &PeripheralsConfig) -> (Config<C, EspSystem<C>>, Vec<SyscallMenu>, Vec<InitError>) {
+/// Sends a message for every (debounced) edge of a digital input, so that programs do not need to poll it.
+/// The pin interrupt only wakes a background thread, which waits for the value to settle before reading it.
+/// When dropped, the interrupt is removed and the thread is stopped.
+struct EdgeWatcher {
+    pin: SharedPin<DigitalInPinDriver>,
+    stop: Arc<AtomicBool>,
+    _thread: JoinOnDrop, // must be dropped after stop is set
+}
+impl EdgeWatcher {
+    fn new(pin: SharedPin<DigitalInPinDriver>, entry: &DigitalIn, edge: Edge, messages: Sender<IncomingMessage>) -> Result<Self, EspError> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (notifier_sender, notifier_receiver) = channel();
+        let thread = {
+            let (pin, stop) = (pin.clone(), stop.clone());
+            let (name, negated, debounce_ms) = (CompactString::from(entry.name.as_str()), entry.negated, entry.debounce_ms);
+            let msg_type = CompactString::from(entry.msg_type.as_deref().unwrap_or(&entry.name));
+            thread::spawn(move || {
+                let notification = Notification::new(); // must be created by the thread that waits on it
+                notifier_sender.send(notification.notifier()).unwrap();
+
+                let mut last = pin.0.lock().unwrap().is_high() ^ negated;
+                while !stop.load(Ordering::Relaxed) {
+                    if notification.wait(TickType::new_millis(EDGE_POLL_INTERVAL_MS).ticks()).is_none() { continue }
+
+                    // wait until there have been no edges for the whole debounce period (each bounce restarts the wait)
+                    loop {
+                        let _ = pin.0.lock().unwrap().enable_interrupt(); // interrupts are disabled after each trigger
+                        if stop.load(Ordering::Relaxed) || notification.wait(TickType::new_millis(debounce_ms as u64).ticks()).is_none() { break }
+                    }
+                    if stop.load(Ordering::Relaxed) { break }
+
+                    let value = pin.0.lock().unwrap().is_high() ^ negated;
+                    if value == last { continue }
+                    last = value;
+
+                    if edge.matches(value) {
+                        let values = [("name".into(), SimpleValue::String(name.clone())), ("value".into(), SimpleValue::Bool(value))].into_iter().collect();
+                        let _ = messages.send(IncomingMessage { msg_type: msg_type.clone(), values, reply_key: None });
+                    }
+                }
+            })
+        };
+        let notifier = notifier_receiver.recv().unwrap();
+        let res = Self { pin, stop, _thread: JoinOnDrop(Some(thread)) };
+
+        {
+            let mut pin = res.pin.0.lock().unwrap();
+            pin.set_interrupt_type(InterruptType::AnyEdge)?;
+            unsafe { pin.subscribe(move || { notifier.notify_and_yield(NonZeroU32::new(1).unwrap()); })?; }
+            pin.enable_interrupt()?;
+        }
+
+        Ok(res)
+    }
+}
+impl Drop for EdgeWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.pin.0.lock().unwrap().unsubscribe();
+    }
+}
+
+/// Waits for a thread to finish when dropped, so that anything it owns (e.g., a pwm channel) is released by then.
+struct JoinOnDrop(Option<thread::JoinHandle<()>>);
+impl Drop for JoinOnDrop {
+    fn drop(&mut self) {
+        if let Some(thread) = self.0.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Plays tones on a buzzer from a background thread so that playback does not block the vm.
+/// Starting a new playback (or stopping) interrupts whatever was playing before.
+/// Once every clone is dropped, the thread stops and the buzzer's pwm channel is released.
+#[derive(Clone)]
+struct BuzzerController {
+    playback: Sender<BuzzerPlayback>, // must be dropped before the thread is joined
+    _thread: Rc<JoinOnDrop>,
+}
+impl BuzzerController {
+    fn new<P: SetDutyCycle + Send + 'static>(pwm: P, timer: u8) -> Self {
+        let (playback, receiver) = channel();
+        let thread = thread::spawn(move || Self::run(pwm, timer, receiver));
+        Self { playback, _thread: Rc::new(JoinOnDrop(Some(thread))) }
+    }
+    /// Starts playing the tones, completing `done` (if present) once they are finished or interrupted.
+    fn play(&self, tones: Vec<Tone>, done: Option<CommandKey>) {
+        let _ = self.playback.send(BuzzerPlayback { tones, done });
+    }
+    fn stop(&self) {
+        self.play(vec![], None);
+    }
+    fn run<P: SetDutyCycle>(mut pwm: P, timer: u8, receiver: Receiver<BuzzerPlayback>) {
+        let mut tones = Vec::<Tone>::new().into_iter();
+        let mut done: Option<CommandKey> = None;
+        loop {
+            let next = match tones.next() {
+                Some(tone) => {
+                    let frequency = tone.frequency.round().min(u32::MAX as f64) as u32;
+                    let audible = check_pwm_frequency(frequency, BUZZER_RESOLUTION_BITS).and_then(|()| set_timer_frequency(timer, frequency)).is_ok();
+                    let _ = if audible { pwm.set_duty_cycle_percent(50) } else { pwm.set_duty_cycle_fully_off() };
+                    match receiver.recv_timeout(tone.duration) {
+                        Ok(x) => x,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => {
+                    let _ = pwm.set_duty_cycle_fully_off();
+                    if let Some(done) = done.take() {
+                        done.complete(Ok(()));
+                    }
+                    match receiver.recv() {
+                        Ok(x) => x,
+                        Err(_) => break,
+                    }
+                }
+            };
+            if let Some(done) = done.take() {
+                done.complete(Ok(()));
+            }
+            tones = next.tones.into_iter();
+            done = next.done;
+        }
+
+        // the controller was dropped, so silence the buzzer and release anyone still waiting on it
+        let _ = pwm.set_duty_cycle_fully_off();
+        if let Some(done) = done {
+            done.complete(Ok(()));
+        }
+    }
+}
+
+/// Something that can send raw color data to a strip of ws2812 (neopixel) leds.
+trait PixelStrip {
+    fn write(&mut self, data: &[u8]) -> Result<(), EspError>;
+}
+impl PixelStrip for TxRmtDriver<'_> {
+    fn write(&mut self, data: &[u8]) -> Result<(), EspError> {
+        let ticks_hz = self.counter_clock()?;
+        let pulse = |state, nanos| Pulse::new_with_duration(ticks_hz, state, &Duration::from_nanos(nanos));
+        let zero = [pulse(rmt::PinState::High, 400)?, pulse(rmt::PinState::Low, 850)?];
+        let one = [pulse(rmt::PinState::High, 800)?, pulse(rmt::PinState::Low, 450)?];
+
+        let mut signal = VariableLengthSignal::new();
+        for byte in data {
+            for bit in (0..8).rev() {
+                signal.push(if (byte >> bit) & 1 != 0 { &one } else { &zero })?;
+            }
+        }
+        self.start_blocking(&signal)
+    }
+}
+
+/// Buffers the colors of a neopixel strip, which are only sent to the strip by [`NeoPixelController::show`].
+struct NeoPixelController<T: PixelStrip> {
+    strip: T,
+    pixels: Vec<[u8; 3]>,
+    color_order: ColorOrder,
+    brightness: f64,
+}
+impl<T: PixelStrip> NeoPixelController<T> {
+    fn set_brightness(&mut self, percent: f64) {
+        self.brightness = percent.clamp(0.0, 100.0) / 100.0;
+    }
+    fn show(&mut self) -> Result<(), EspError> {
+        let mut data = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel in self.pixels.iter() {
+            data.extend(self.color_order.arrange(pixel.map(|x| (x as f64 * self.brightness).round() as u8)));
+        }
+        self.strip.write(&data)
+    }
+}
+
+/// Parses an `[r, g, b]` color, where each component is an integer in `[0, 255]`.
+fn parse_color<C: CustomTypes<S>, S: System<C>>(value: &Value<'_, C, S>) -> Option<[u8; 3]> {
+    let color = value.as_list().ok()?;
+    let color = color.borrow();
+    if color.len() != 3 {
+        return None;
+    }
+    let mut res = [0; 3];
+    for (res, value) in iter::zip(&mut res, color.iter()) {
+        let raw = value.as_number().ok()?.get();
+        *res = raw as u8;
+        if *res as f64 != raw {
+            return None;
+        }
+    }
+    Some(res)
+}
+
+#[derive(Default)]
+struct IS31FL3741Recording {
+    page: u8,
+    writes: Vec<(u8, u8, u8)>, // page, register, value
+}
+
+/// A fake i2c bus which records the register writes made by the is31fl3741 driver.
+#[derive(Clone, Default)]
+struct IS31FL3741Recorder(Rc<RefCell<IS31FL3741Recording>>);
+impl embedded_hal::i2c::ErrorType for IS31FL3741Recorder {
+    type Error = core::convert::Infallible;
+}
+impl I2c for IS31FL3741Recorder {
+    fn transaction(&mut self, _: u8, operations: &mut [I2cOperation<'_>]) -> Result<(), Self::Error> {
+        let mut recording = self.0.borrow_mut();
+        for op in operations.iter() {
+            match op {
+                I2cOperation::Write([0xfe, _]) => (), // command register unlock
+                I2cOperation::Write([0xfd, page]) => recording.page = *page,
+                I2cOperation::Write([register, value]) => {
+                    let page = recording.page;
+                    recording.writes.push((page, *register, *value));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drives an is31fl3741 led matrix, which can also be updated a whole frame at a time (one i2c write per pwm page)
+/// rather than one transaction per color channel of each pixel.
+struct IS31FL3741Controller<I: I2c> {
+    device: is31fl3741::devices::AdafruitRGB13x9<I>,
+    i2c: I,
+    i2c_addr: u8,
+    layout: Vec<[usize; 3]>, // frame index of the r, g, b channels of each pixel (row-major)
+    pixels: Vec<[u8; 3]>,
+}
+impl<I: I2c + Clone> IS31FL3741Controller<I> {
+    fn new(device: is31fl3741::devices::AdafruitRGB13x9<I>, i2c: I, i2c_addr: u8) -> Option<Self> {
+        let layout = Self::learn_layout(i2c_addr)?;
+        Some(Self { device, i2c, i2c_addr, pixels: vec![[0; 3]; layout.len()], layout })
+    }
+    /// The led wiring of the matrix is only known to the driver, so we find where it puts each color channel
+    /// by having it draw every pixel to a fake i2c bus and seeing which registers it writes.
+    fn learn_layout(i2c_addr: u8) -> Option<Vec<[usize; 3]>> {
+        let recorder = IS31FL3741Recorder::default();
+        let mut device = is31fl3741::devices::AdafruitRGB13x9::configure(recorder.clone(), i2c_addr);
+        let mut layout = Vec::with_capacity(IS31FL3741_WIDTH as usize * IS31FL3741_HEIGHT as usize);
+        for y in 0..IS31FL3741_HEIGHT {
+            for x in 0..IS31FL3741_WIDTH {
+                recorder.0.borrow_mut().writes.clear();
+                device.pixel_rgb(x, y, 1, 2, 3).ok()?;
+                let mut channels = [None; 3];
+                for (page, register, value) in recorder.0.borrow().writes.iter().copied() {
+                    if register as usize >= *IS31FL3741_PWM_PAGES.get(page as usize)? {
+                        return None;
+                    }
+                    let offset = IS31FL3741_PWM_PAGES[..page as usize].iter().sum::<usize>();
+                    *channels.get_mut((value as usize).checked_sub(1)?)? = Some(offset + register as usize);
+                }
+                layout.push([channels[0]?, channels[1]?, channels[2]?]);
+            }
+        }
+        Some(layout)
+    }
+    fn set_pixel(&mut self, x: u8, y: u8, color: [u8; 3]) -> Result<(), is31fl3741::Error<I::Error>> {
+        let [r, g, b] = color;
+        self.pixels[y as usize * IS31FL3741_WIDTH as usize + x as usize] = color;
+        self.device.pixel_rgb(x, y, r, g, b)
+    }
+    /// Writes all the pixels to the device.
+    fn show(&mut self) -> Result<(), I::Error> {
+        let mut frame = vec![0u8; IS31FL3741_PWM_PAGES.iter().sum()];
+        for (pixel, channels) in iter::zip(&self.pixels, &self.layout) {
+            for (value, index) in iter::zip(pixel, channels) {
+                frame[*index] = *value;
+            }
+        }
+
+        let mut start = 0;
+        for (page, size) in IS31FL3741_PWM_PAGES.iter().copied().enumerate() {
+            self.i2c.write(self.i2c_addr, &[0xfe, 0xc5])?; // unlock the command register
+            self.i2c.write(self.i2c_addr, &[0xfd, page as u8])?;
+            let mut data = Vec::with_capacity(size + 1);
+            data.push(0x00); // starting register (auto-increments)
+            data.extend_from_slice(&frame[start..start + size]);
+            self.i2c.write(self.i2c_addr, &data)?;
+            start += size;
+        }
+        Ok(())
+    }
+    fn set_brightness(&mut self, percent: f64) -> Result<(), I::Error> {
+        self.device.set_scaling((percent.clamp(0.0, 100.0) * 2.55).round() as u8)
+    }
+}
+
+/// Drives a 128-pixel wide monochrome oled display with an ssd1306 or sh1106 controller.
+/// Drawing only changes the frame buffer, which is not sent to the display until [`OledController::flush`].
+struct OledController<I: I2c> {
+    i2c: I,
+    i2c_addr: u8,
+    driver: OledDriver,
+    height: usize,
+    buffer: Vec<u8>, // one byte per column of each 8-row page, with the top row in the lowest bit
+}
+impl<I: I2c> OledController<I> {
+    fn new(i2c: I, i2c_addr: u8, driver: OledDriver, height: usize) -> Result<Self, I::Error> {
+        let mut res = Self { i2c, i2c_addr, driver, height, buffer: vec![0; OLED_WIDTH * height / 8] };
+        let mut init = vec![
+            0xae, // display off
+            0xd5, 0x80, // clock divider
+            0xa8, height as u8 - 1, // multiplex ratio
+            0xd3, 0x00, // display offset
+            0x40, // start line
+            0xa1, 0xc8, // flip horizontally and vertically (so that the pins are on top)
+            0xda, if height == 64 { 0x12 } else { 0x02 }, // com pin layout
+            0x81, 0xcf, // contrast
+            0xd9, 0xf1, // precharge period
+            0xdb, 0x40, // vcomh level
+            0xa4, 0xa6, // show ram contents, not inverted
+        ];
+        match driver {
+            OledDriver::Ssd1306 => init.extend([0x8d, 0x14, 0x20, 0x00]), // charge pump on, horizontal addressing
+            OledDriver::Sh1106 => init.extend([0xad, 0x8b]), // dc-dc converter on
+        }
+        res.command(&init)?;
+        res.flush()?; // the display ram is not cleared on reset
+        res.command(&[0xaf])?; // display on
+        Ok(res)
+    }
+    fn command(&mut self, commands: &[u8]) -> Result<(), I::Error> {
+        let mut data = Vec::with_capacity(commands.len() + 1);
+        data.push(0x00); // command stream
+        data.extend_from_slice(commands);
+        self.i2c.write(self.i2c_addr, &data)
+    }
+    fn clear(&mut self) {
+        self.buffer.fill(0);
+    }
+    fn show_image(&mut self, pixels: &[bool]) {
+        self.clear();
+        for (i, _) in pixels.iter().enumerate().filter(|x| *x.1) {
+            let (x, y) = (i % OLED_WIDTH, i / OLED_WIDTH);
+            self.buffer[(y / 8) * OLED_WIDTH + x] |= 1 << (y % 8);
+        }
+    }
+    fn flush(&mut self) -> Result<(), I::Error> {
+        for page in 0..self.height / 8 {
+            match self.driver {
+                OledDriver::Ssd1306 => self.command(&[0x21, 0, OLED_WIDTH as u8 - 1, 0x22, page as u8, page as u8])?,
+                OledDriver::Sh1106 => self.command(&[0xb0 + page as u8, 0x02, 0x10])?, // the sh1106 has 132 columns of ram, with the visible ones centered
+            }
+            let mut data = Vec::with_capacity(OLED_WIDTH + 1);
+            data.push(0x40); // data stream
+            data.extend_from_slice(&self.buffer[page * OLED_WIDTH..(page + 1) * OLED_WIDTH]);
+            self.i2c.write(self.i2c_addr, &data)?;
+        }
+        Ok(())
+    }
+}
+impl<I: I2c> OriginDimensions for OledController<I> {
+    fn size(&self) -> Size {
+        Size::new(OLED_WIDTH as u32, self.height as u32)
+    }
+}
+impl<I: I2c> DrawTarget for OledController<I> {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<T: IntoIterator<Item = Pixel<BinaryColor>>>(&mut self, pixels: T) -> Result<(), Self::Error> {
+        for Pixel(point, color) in pixels {
+            let (x, y) = match (usize::try_from(point.x), usize::try_from(point.y)) {
+                (Ok(x), Ok(y)) if x < OLED_WIDTH && y < self.height => (x, y),
+                _ => continue,
+            };
+            let byte = &mut self.buffer[(y / 8) * OLED_WIDTH + x];
+            match color {
+                BinaryColor::On => *byte |= 1 << (y % 8),
+                BinaryColor::Off => *byte &= !(1 << (y % 8)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum St7789Error<S, P> {
+    Spi(S),
+    Pin(P),
+}
+
+/// Drives an st7789 tft display over spi.
+/// Drawing only changes the frame buffer, which is not sent to the display until [`St7789Controller::flush`].
+struct St7789Controller<S: SpiDevice, P: OutputPin> {
+    spi: S,
+    dc: P,
+    width: u16,
+    height: u16,
+    offset: [u16; 2],
+    color: Rgb565,
+    buffer: Vec<u16>, // row-major rgb565 pixels
+}
+impl<S: SpiDevice, P: OutputPin> St7789Controller<S, P> {
+    fn new(spi: S, dc: P, rst: Option<P>, width: u16, height: u16, offset: [u16; 2]) -> Result<Self, St7789Error<S::Error, P::Error>> {
+        let mut res = Self { spi, dc, width, height, offset, color: Rgb565::new(31, 63, 31), buffer: vec![0; width as usize * height as usize] };
+        if let Some(mut rst) = rst {
+            rst.set_low().map_err(St7789Error::Pin)?;
+            thread::sleep(Duration::from_millis(10));
+            rst.set_high().map_err(St7789Error::Pin)?;
+        }
+        res.command(0x01, &[])?; // software reset
+        thread::sleep(Duration::from_millis(150));
+        res.command(0x11, &[])?; // sleep out
+        thread::sleep(Duration::from_millis(120));
+        res.command(0x3a, &[0x55])?; // 16-bit color
+        res.command(0x36, &[0x00])?; // top to bottom, left to right, rgb order
+        res.command(0x21, &[])?; // inversion on (needed by nearly every st7789 module)
+        res.command(0x13, &[])?; // normal display mode
+        res.flush()?; // the display ram is not cleared on reset
+        res.command(0x29, &[])?; // display on
+        Ok(res)
+    }
+    fn command(&mut self, command: u8, data: &[u8]) -> Result<(), St7789Error<S::Error, P::Error>> {
+        self.dc.set_low().map_err(St7789Error::Pin)?;
+        self.spi.write(&[command]).map_err(St7789Error::Spi)?;
+        if !data.is_empty() {
+            self.dc.set_high().map_err(St7789Error::Pin)?;
+            self.spi.write(data).map_err(St7789Error::Spi)?;
+        }
+        Ok(())
+    }
+    fn show_image(&mut self, pixels: &[[u8; 3]]) {
+        for (dest, &[r, g, b]) in self.buffer.iter_mut().zip(pixels) {
+            *dest = Rgb565::new(r >> 3, g >> 2, b >> 3).into_storage();
+        }
+    }
+    fn flush(&mut self) -> Result<(), St7789Error<S::Error, P::Error>> {
+        let [x, y] = self.offset;
+        let [x_end, y_end] = [x + self.width - 1, y + self.height - 1];
+        self.command(0x2a, &[(x >> 8) as u8, x as u8, (x_end >> 8) as u8, x_end as u8])?; // column range
+        self.command(0x2b, &[(y >> 8) as u8, y as u8, (y_end >> 8) as u8, y_end as u8])?; // row range
+        self.command(0x2c, &[])?; // memory write
+
+        // the frame buffer is streamed in chunks rather than converted all at once, which would need a second copy of it.
+        // the display keeps writing to its ram until the next command, so each chunk can be a separate spi transaction.
+        self.dc.set_high().map_err(St7789Error::Pin)?;
+        let mut chunk = [0u8; ST7789_FLUSH_CHUNK_BYTES];
+        for pixels in self.buffer.chunks(ST7789_FLUSH_CHUNK_BYTES / 2) {
+            for (dest, pixel) in chunk.chunks_exact_mut(2).zip(pixels) {
+                dest.copy_from_slice(&pixel.to_be_bytes());
+            }
+            self.spi.write(&chunk[..2 * pixels.len()]).map_err(St7789Error::Spi)?;
+        }
+        Ok(())
+    }
+}
+impl<S: SpiDevice, P: OutputPin> OriginDimensions for St7789Controller<S, P> {
+    fn size(&self) -> Size {
+        Size::new(self.width as u32, self.height as u32)
+    }
+}
+impl<S: SpiDevice, P: OutputPin> DrawTarget for St7789Controller<S, P> {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<T: IntoIterator<Item = Pixel<Rgb565>>>(&mut self, pixels: T) -> Result<(), Self::Error> {
+        for Pixel(point, color) in pixels {
+            let (x, y) = match (usize::try_from(point.x), usize::try_from(point.y)) {
+                (Ok(x), Ok(y)) if x < self.width as usize && y < self.height as usize => (x, y),
+                _ => continue,
+            };
+            self.buffer[y * self.width as usize + x] = color.into_storage();
+        }
+        Ok(())
+    }
+}
+
+/// An sd card with a fat filesystem, which is accessed through the vfs.
+struct SdCardController {
+    root: PathBuf,
+    _mount: SdCardMount,
+}
+impl SdCardController {
+    /// Gets the vfs path of a file on the card, or `None` if the path tries to escape the card.
+    fn path(&self, path: &str) -> Option<PathBuf> {
+        let mut res = self.root.clone();
+        for part in path.split('/').filter(|x| !x.is_empty() && *x != ".") {
+            if part == ".." { return None }
+            res.push(part);
+        }
+        Some(res)
+    }
+}
+
+#[cfg(target_os = "espidf")]
+fn vfs_path(mount_point: &str) -> PathBuf {
+    mount_point.into()
+}
+#[cfg(not(target_os = "espidf"))]
+fn vfs_path(mount_point: &str) -> PathBuf {
+    crate::sim::vfs_path(mount_point)
+}
+
+struct DigitalInController<P: InputPin> {
+    pin: P,
+    negated: bool,
+    _watcher: Option<EdgeWatcher>,
+}
+impl<P: InputPin> DigitalInController<P> {
+    fn get_value(&mut self) -> Result<bool, P::Error> {
+        Ok(self.pin.is_high()? ^ self.negated)
+    }
+}
+
+struct AnalogInController {
+    channel: Box<dyn AnalogChannel>,
+    samples: u16,
+}
+impl AnalogInController {
+    fn average(&mut self, read: fn(&mut dyn AnalogChannel) -> Result<u16, EspError>) -> Result<f64, EspError> {
+        let mut total = 0u32;
+        for _ in 0..self.samples {
+            total += read(&mut *self.channel)? as u32;
+        }
+        Ok(total as f64 / self.samples as f64)
+    }
+    fn get_raw(&mut self) -> Result<f64, EspError> {
+        self.average(|x| x.read_raw())
+    }
+    fn get_voltage(&mut self) -> Result<f64, EspError> {
+        Ok(self.average(|x| x.read_mv())? / 1000.0)
+    }
+}
+
+struct DigitalOutController<P: OutputPin> {
+    pin: P,
+    negated: bool,
+}
+impl<P: OutputPin> DigitalOutController<P> {
+    fn set_value(&mut self, value: bool) -> Result<(), P::Error> {
+        self.pin.set_state(PinState::from(value ^ self.negated))
+    }
+}
+
+#[derive(Debug)]
+enum HCSR04Error<T, E> {
+    Trigger(T),
+    Echo(E),
+}
+
+struct HCSR04Controller<T: OutputPin, E: InputPin, D: DelayNs> {
+    trigger: T,
+    echo: E,
+    delay: D,
+}
+impl<T: OutputPin, E: InputPin, D: DelayNs> HCSR04Controller<T, E, D> {
+    fn get_distance(&mut self) -> Result<f64, HCSR04Error<T::Error, E::Error>> {
+        self.trigger.set_high().map_err(HCSR04Error::Trigger)?;
+        self.delay.delay_us(10);
+        self.trigger.set_low().map_err(HCSR04Error::Trigger)?;
+        let duration = measure_pulse(&mut self.echo, PinState::High, Duration::from_millis(50)).map_err(HCSR04Error::Echo)?.map(|x| x.as_micros()).unwrap_or(0);
+        Ok(duration as f64 * 0.01715) // half (because round trip) the speed of sound in cm/us
+    }
+}
+
+// -----------------------------------------------------------------
+
+pub struct SyscallPeripherals {
+    pub pins: Pins,
+    pub ledc: LEDC,
+    pub rmt: RMT,
+    pub adc1: ADC1,
+    pub i2c0: I2C0,
+    pub i2c1: I2C1,
+    pub spi: SPI2,
+}
+
+impl SyscallPeripherals {
+    /// Takes back the peripherals that were given to a previous call to [`bind_syscalls`] so that they can be bound again.
+    ///
+    /// # Safety
+    /// Everything returned by the previous call to [`bind_syscalls`] must already be dropped.
+    /// Otherwise, the old drivers would still be using the peripherals.
+    pub unsafe fn reclaim() -> Self {
+        let peripherals = Peripherals::steal();
+        Self {
+            pins: peripherals.pins,
+            ledc: peripherals.ledc,
+            rmt: peripherals.rmt,
+            adc1: peripherals.adc1,
+            i2c0: peripherals.i2c0,
+            i2c1: peripherals.i2c1,
+            spi: peripherals.spi2,
+        }
+    }
+}
+
+pub struct InitError {
+    pub context: String,
+    pub error: PeripheralError,
+}
+
+/// Binds the syscalls for the given peripherals config.
+/// Messages sent by peripherals (e.g., digital input edges) are sent to `messages`, which should be received by the [`EspSystem`].
+pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &PeripheralsConfig, messages: &Sender<IncomingMessage>) -> (Config<C, EspSystem<C>>, Vec<SyscallMenu>, Vec<InitError>, I2cScanner) {
+    let (peripheral_handles, sound_buzzer, syscalls, errors, i2c_scanner) = init_peripherals(peripherals, peripherals_config, messages);
+    let peripheral_handles = RefCell::new(peripheral_handles);
+
+    let config = Config::<C, _> {
+        request: Some(Rc::new(move |_, key, request, _| handle_request(&peripheral_handles, key, request))),
+        command: Some(Rc::new(move |_, key, command, _| match (&command, &sound_buzzer) {
+            (Command::PlayNotes { notes, beats, blocking }, Some(buzzer)) => {
+                // a buzzer can only play one note at a time, so chords are reduced to their highest note
+                let frequency = notes.iter().map(|x| x.get_frequency().get()).fold(0.0, f64::max);
+                let tone = match Tone::new(frequency, beats.get() * BUZZER_SOUND_SECS_PER_BEAT) {
+                    Some(x) => x,
+                    None => {
+                        key.complete(Err(format_compact!("failed to play notes: invalid number of beats {}", beats.get())));
+                        return CommandStatus::Handled;
+                    }
+                };
+                match blocking {
+                    true => buzzer.play(vec![tone], Some(key)),
+                    false => {
+                        buzzer.play(vec![tone], None);
+                        key.complete(Ok(()));
+                    }
+                }
+                CommandStatus::Handled
+            }
+            (Command::StopSounds, Some(buzzer)) => {
+                buzzer.stop();
+                key.complete(Ok(()));
+                CommandStatus::Handled
+            }
+            _ => CommandStatus::UseDefault { key, command },
+        })),
+    };
+
+    (config, syscalls, errors, i2c_scanner)
+}
+
+/// Sets up the drivers for every peripheral in the config, along with the syscall menu and any errors that occurred.
+/// The first buzzer (if any) is also returned for the sound blocks.
+fn init_peripherals(peripherals: SyscallPeripherals, peripherals_config: &PeripheralsConfig, messages: &Sender<IncomingMessage>) -> (PeripheralHandles, Option<BuzzerController>, Vec<SyscallMenu>, Vec<InitError>, I2cScanner) {
     let mut syscalls = vec![];
     let mut errors = vec![];
 
     let mut pins = GpioManager::new(peripherals.pins);
-    let mut pwms = match PwmManager::new(peripherals.ledc) {
+    let mut pwms = PwmManager::new(peripherals.ledc);
+    let mut rmts = RmtManager::new(peripherals.rmt);
+    let mut i2cs = I2cManager::new(peripherals.i2c0, peripherals.i2c1);
+
+    let mut adcs = match AdcManager::new(peripherals.adc1) {
         Ok(x) => Some(x),
         Err(e) => {
-            errors.push(InitError { context: "PWM".into(), error: e.into() });
+            errors.push(InitError { context: "ADC".into(), error: e.into() });
             None
         }
     };
 
     // -------------------------------------------------------------
 
-    let i2c = match &peripherals_config.i2c {
-        Some(i2c) => {
-            match pins.take_convert(i2c.gpio_sda, AnyPin::try_into_input_output) {
-                Ok(sda) => match pins.take_convert(i2c.gpio_scl, AnyPin::try_into_input_output) {
-                    Ok(scl) => match I2cDriver::new(peripherals.i2c, sda, scl, &Default::default()) {
-                        Ok(i2c) => Some(SharedI2c::new(i2c)),
+    let i2c_buses = {
+        let mut res = BTreeMap::new();
+        for entry in peripherals_config.i2c.iter().chain(peripherals_config.i2c_buses.iter()) {
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("I2C {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            let sda = match pins.take_convert(entry.gpio_sda, AnyPin::try_into_input_output) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("I2C {} gpio_sda", entry.name), error: e });
+                    continue
+                }
+            };
+            let scl = match pins.take_convert(entry.gpio_scl, AnyPin::try_into_input_output) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("I2C {} gpio_scl", entry.name), error: e });
+                    continue
+                }
+            };
+            let config = I2cConfig::new().baudrate(entry.frequency.Hz()).sda_enable_pullup(entry.pullups).scl_enable_pullup(entry.pullups);
+            match i2cs.take(sda, scl, &config) {
+                Ok(i2c) => { res.insert(entry.name.clone(), SharedI2c::new(i2c)); }
+                Err(e) => errors.push(InitError { context: format!("I2C {}", entry.name), error: e }),
+            }
+        }
+        res
+    };
+
+    let spi = match &peripherals_config.spi {
+        Some(spi) => {
+            match pins.take_convert(spi.gpio_sclk, AnyPin::try_into_output) {
+                Ok(sclk) => match pins.take_convert(spi.gpio_mosi, AnyPin::try_into_output) {
+                    Ok(mosi) => match spi.gpio_miso.map(|x| pins.take_convert(x, AnyPin::try_into_input)).transpose() {
+                        Ok(miso) => match SpiDriver::new(peripherals.spi, sclk, mosi, miso, &SpiDriverConfig::new().dma(Dma::Auto(SPI_DMA_BUFFER_SIZE))) {
+                            Ok(spi) => Some(Rc::new(spi)),
+                            Err(e) => {
+                                errors.push(InitError { context: "SPI".into(), error: e.into() });
+                                None
+                            }
+                        }
                         Err(e) => {
-                            errors.push(InitError { context: "I2C".into(), error: e.into() });
+                            errors.push(InitError { context: "SPI gpio_miso".into(), error: e });
                             None
                         }
                     }
                     Err(e) => {
-                        errors.push(InitError { context: "I2C gpio_scl".into(), error: e.into() });
+                        errors.push(InitError { context: "SPI gpio_mosi".into(), error: e });
                         None
                     }
                 }
                 Err(e) => {
-                    errors.push(InitError { context: "I2C gpio_sda".into(), error: e.into() });
+                    errors.push(InitError { context: "SPI gpio_sclk".into(), error: e });
                     None
                 }
             }
@@ -435,8 +1894,16 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.digital_ins.len());
 
         for entry in peripherals_config.digital_ins.iter() {
-            let pin = match pins.take_convert(entry.gpio, AnyPin::try_into_input).and_then(|x| PinDriver::input(x).map_err(Into::into)) {
-                Ok(x) => x,
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("digital_ins {}", entry.name), error });
+                continue
+            }
+            let pin = match pins.take_convert(entry.gpio, AnyPin::try_into_input_output).and_then(|x| {
+                let mut pin = PinDriver::input(x)?;
+                pin.set_pull(entry.pull.to_pull())?;
+                Ok(pin)
+            }) {
+                Ok(x) => SharedPin::new(x),
                 Err(error) => {
                     errors.push(InitError { context: format!("digital_ins {} gpio", entry.name), error });
                     continue
@@ -446,7 +1913,14 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
                 errors.push(InitError { context: format!("digital_ins {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
                 continue
             }
-            res.insert(entry.name.clone(), DigitalInController { pin, negated: entry.negated });
+            let watcher = match entry.edge.map(|edge| EdgeWatcher::new(pin.clone(), entry, edge, messages.clone())).transpose() {
+                Ok(x) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("digital_ins {} edge", entry.name), error: error.into() });
+                    continue
+                }
+            };
+            res.insert(entry.name.clone(), DigitalInController { pin, negated: entry.negated, _watcher: watcher });
             menu_content.push(menu_entries!("DigitalIn", entry.name => "get"));
         }
         if !menu_content.is_empty() {
@@ -461,7 +1935,14 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.digital_outs.len());
 
         for entry in peripherals_config.digital_outs.iter() {
-            let pin = match pins.take_convert(entry.gpio, AnyPin::try_into_output).and_then(|x| PinDriver::output(x).map_err(Into::into)) {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("digital_outs {}", entry.name), error });
+                continue
+            }
+            let pin = match pins.take_convert(entry.gpio, AnyPin::try_into_input_output).and_then(|x| match entry.drive {
+                DriveMode::PushPull => PinDriver::output(x).map_err(Into::into),
+                DriveMode::OpenDrain => PinDriver::output_od(x).map_err(Into::into),
+            }) {
                 Ok(x) => x,
                 Err(error) => {
                     errors.push(InitError { context: format!("digital_outs {} gpio", entry.name), error });
@@ -482,6 +1963,43 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         res
     };
 
+    let analog_ins = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.analog_ins.len());
+
+        for entry in peripherals_config.analog_ins.iter() {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("analog_ins {}", entry.name), error });
+                continue
+            }
+            let adcs = match adcs.as_mut() {
+                Some(x) => x,
+                None => {
+                    errors.push(InitError { context: format!("analog_ins {}", entry.name), error: PeripheralError::Other { cause: "ADC failed to initialize".into() } });
+                    continue
+                }
+            };
+            let channel = match pins.take_convert(entry.gpio, AnyPin::try_into_analog).and_then(|x| adcs.take(x, entry.attenuation)) {
+                Ok(x) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("analog_ins {} gpio", entry.name), error });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("analog_ins {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            res.insert(entry.name.clone(), AnalogInController { channel, samples: entry.samples });
+            menu_content.push(menu_entries!("AnalogIn", entry.name => "getVoltage", "getRaw"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "AnalogIn".into(), content: menu_content });
+        }
+
+        res
+    };
+
     let motor_groups = {
         let mut motors = BTreeMap::new();
         let mut res = BTreeMap::new();
@@ -490,28 +2008,21 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let make_menu_entries = |name: &str| menu_entries!("Motor", name => "setPower");
 
         for entry in peripherals_config.motors.iter() {
-            let pwms = match pwms.as_mut() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("motors {}", entry.name), error: PeripheralError::PwmOutOfChannels });
-                    continue
-                }
-            };
-            let positive = match pins.take_convert(entry.gpio_pos, AnyPin::try_into_output).and_then(|x| pwms.take(x)) {
+            let positive = match pins.take_convert(entry.gpio_pos, AnyPin::try_into_output).and_then(|x| pwms.take(x, PwmTimerConfig::motor())) {
                 Ok(x) => x,
                 Err(error) => {
                     errors.push(InitError { context: format!("motors {} gpio_pos", entry.name), error });
                     continue
                 }
             };
-            let negative = match pins.take_convert(entry.gpio_neg, AnyPin::try_into_output).and_then(|x| pwms.take(x)) {
+            let negative = match pins.take_convert(entry.gpio_neg, AnyPin::try_into_output).and_then(|x| pwms.take(x, PwmTimerConfig::motor())) {
                 Ok(x) => x,
                 Err(error) => {
                     errors.push(InitError { context: format!("motors {} gpio_neg", entry.name), error });
                     continue
                 }
             };
-            let motor = Rc::new(RefCell::new(MotorController { positive, negative }));
+            let motor = Rc::new(RefCell::new(MotorController { positive: positive.0, negative: negative.0 }));
             if motors.contains_key(&entry.name) {
                 errors.push(InitError { context: format!("motors {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
                 continue
@@ -545,11 +2056,135 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         res
     };
 
-    let hcsr04s = {
+    let servos = {
         let mut res = BTreeMap::new();
-        let mut menu_content = Vec::with_capacity(peripherals_config.hcsr04s.len());
+        let mut menu_content = Vec::with_capacity(peripherals_config.servos.len());
 
-        for entry in peripherals_config.hcsr04s.iter() {
+        for entry in peripherals_config.servos.iter() {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("servos {}", entry.name), error });
+                continue
+            }
+            let pwm = match pins.take_convert(entry.gpio, AnyPin::try_into_output).and_then(|x| pwms.take(x, PwmTimerConfig::servo())) {
+                Ok((x, _)) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("servos {} gpio", entry.name), error });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("servos {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            let (min_pulse_us, max_pulse_us, min_angle, max_angle) = (entry.min_pulse_us, entry.max_pulse_us, entry.min_angle, entry.max_angle);
+            res.insert(entry.name.clone(), ServoController { pwm, min_pulse_us, max_pulse_us, min_angle, max_angle });
+            menu_content.push(menu_entries!("Servo", entry.name => "setAngle", "release"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "Servo".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    let pwm_outs = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.pwm_outs.len());
+
+        for entry in peripherals_config.pwm_outs.iter() {
+            let config = PwmTimerConfig { usage: PwmUsage::Output, frequency: entry.frequency, resolution_bits: entry.resolution_bits };
+            let (pwm, timer) = match pins.take_convert(entry.gpio, AnyPin::try_into_output).and_then(|x| pwms.take(x, config)) {
+                Ok(x) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("pwm_outs {} gpio", entry.name), error });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("pwm_outs {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            res.insert(entry.name.clone(), PwmOutController { pwm, timer });
+            menu_content.push(menu_entries!("PWM", entry.name => "setDuty", "setFrequency"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "PWM".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    let mut sound_buzzer = None;
+    let buzzers = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.buzzers.len());
+
+        for entry in peripherals_config.buzzers.iter() {
+            let (pwm, timer) = match pins.take_convert(entry.gpio, AnyPin::try_into_output).and_then(|x| pwms.take(x, PwmTimerConfig::buzzer())) {
+                Ok(x) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("buzzers {} gpio", entry.name), error });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("buzzers {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            let buzzer = BuzzerController::new(pwm, timer);
+            if sound_buzzer.is_none() {
+                sound_buzzer = Some(buzzer.clone()); // the first buzzer also plays the notes from the sound blocks
+            }
+            res.insert(entry.name.clone(), buzzer);
+            menu_content.push(menu_entries!("Buzzer", entry.name => "playTone", "playNotes", "stop"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "Buzzer".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    let neopixels = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.neopixels.len());
+
+        for entry in peripherals_config.neopixels.iter() {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("neopixels {}", entry.name), error });
+                continue
+            }
+            let strip = match pins.take_convert(entry.gpio, AnyPin::try_into_output).and_then(|x| rmts.take(x)) {
+                Ok(x) => x,
+                Err(error) => {
+                    errors.push(InitError { context: format!("neopixels {} gpio", entry.name), error });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("neopixels {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            let mut controller = NeoPixelController { strip, pixels: vec![[0; 3]; entry.count as usize], color_order: entry.color_order, brightness: 1.0 };
+            if let Err(e) = controller.show() { // strips keep their colors through a reset, so clear them
+                errors.push(InitError { context: format!("neopixels {}", entry.name), error: e.into() });
+                continue
+            }
+            res.insert(entry.name.clone(), controller);
+            menu_content.push(menu_entries!("NeoPixel", entry.name => "setPixel", "fill", "setPixels", "setBrightness", "show"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "NeoPixel".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    let hcsr04s = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.hcsr04s.len());
+
+        for entry in peripherals_config.hcsr04s.iter() {
             let trigger = match pins.take_convert(entry.gpio_trigger, AnyPin::try_into_output).and_then(|x| PinDriver::output(x).map_err(Into::into)) {
                 Ok(x) => x,
                 Err(error) => {
@@ -568,7 +2203,7 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
                 errors.push(InitError { context: format!("hcsr04s {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
                 continue
             }
-            res.insert(entry.name.clone(), HCSR04Controller { trigger, echo });
+            res.insert(entry.name.clone(), HCSR04Controller { trigger, echo, delay: Ets });
             menu_content.push(menu_entries!("HCSR04", entry.name => "getDistance"));
         }
         if !menu_content.is_empty() {
@@ -583,10 +2218,10 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.max30205s.len());
 
         for entry in peripherals_config.max30205s.iter() {
-            let i2c = match i2c.clone() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("max30205s {}", entry.name), error: PeripheralError::I2cNotConfigured });
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("max30205s {}", entry.name), error: e });
                     continue
                 }
             };
@@ -616,14 +2251,14 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.is31fl3741s.len());
 
         for entry in peripherals_config.is31fl3741s.iter() {
-            let i2c = match i2c.clone() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("is31fl3741s {}", entry.name), error: PeripheralError::I2cNotConfigured });
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("is31fl3741s {}", entry.name), error: e });
                     continue
                 }
             };
-            let mut device = is31fl3741::devices::AdafruitRGB13x9::configure(i2c, entry.i2c_addr);
+            let mut device = is31fl3741::devices::AdafruitRGB13x9::configure(i2c.clone(), entry.i2c_addr);
             match device.setup(&mut Ets) {
                 Ok(()) => (),
                 Err(is31fl3741::Error::I2cError(e)) => {
@@ -642,12 +2277,23 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
                     continue
                 }
             }
+            let mut controller = match IS31FL3741Controller::new(device, i2c, entry.i2c_addr) {
+                Some(x) => x,
+                None => {
+                    errors.push(InitError { context: format!("is31fl3741s {}", entry.name), error: PeripheralError::Other { cause: "failed to determine led layout".into() } });
+                    continue
+                }
+            };
+            if let Err(e) = controller.show() { // the matrix keeps its pixels through a reset, so clear them
+                errors.push(InitError { context: format!("is31fl3741s {}", entry.name), error: e.into() });
+                continue
+            }
             if res.contains_key(&entry.name) {
                 errors.push(InitError { context: format!("is31fl3741s {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
                 continue
             }
-            res.insert(entry.name.clone(), device);
-            menu_content.push(menu_entries!("IS31FL3741", entry.name => "setPixel"));
+            res.insert(entry.name.clone(), controller);
+            menu_content.push(menu_entries!("IS31FL3741", entry.name => "setPixel", "setPixels", "showImage", "fill", "clear", "setBrightness"));
         }
         if !menu_content.is_empty() {
             syscalls.push(SyscallMenu::Submenu { label: "IS31FL3741".into(), content: menu_content });
@@ -656,15 +2302,52 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         res
     };
 
+    let ssd1306s = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.ssd1306s.len());
+
+        for entry in peripherals_config.ssd1306s.iter() {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("ssd1306s {}", entry.name), error });
+                continue
+            }
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("ssd1306s {}", entry.name), error: e });
+                    continue
+                }
+            };
+            let device = match OledController::new(i2c, entry.i2c_addr, entry.driver, entry.height as usize) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("ssd1306s {}", entry.name), error: e.into() });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("ssd1306s {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            res.insert(entry.name.clone(), device);
+            menu_content.push(menu_entries!("SSD1306", entry.name => "clear", "drawText", "drawLine", "drawRect", "showImage", "flush"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "SSD1306".into(), content: menu_content });
+        }
+
+        res
+    };
+
     let bmp388s = {
         let mut res = BTreeMap::new();
         let mut menu_content = Vec::with_capacity(peripherals_config.bmp388s.len());
 
         for entry in peripherals_config.bmp388s.iter() {
-            let i2c = match i2c.clone() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("bmp388s {}", entry.name), error: PeripheralError::I2cNotConfigured });
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("bmp388s {}", entry.name), error: e });
                     continue
                 }
             };
@@ -701,10 +2384,10 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.lis3dhs.len());
 
         for entry in peripherals_config.lis3dhs.iter() {
-            let i2c = match i2c.clone() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("lis3dhs {}", entry.name), error: PeripheralError::I2cNotConfigured });
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("lis3dhs {}", entry.name), error: e });
                     continue
                 }
             };
@@ -738,10 +2421,10 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         let mut menu_content = Vec::with_capacity(peripherals_config.veml7700s.len());
 
         for entry in peripherals_config.veml7700s.iter() {
-            let i2c = match i2c.clone() {
-                Some(x) => x,
-                None => {
-                    errors.push(InitError { context: format!("veml7700s {}", entry.name), error: PeripheralError::I2cNotConfigured });
+            let i2c = match get_i2c_bus(&i2c_buses, &entry.i2c_bus) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("veml7700s {}", entry.name), error: e });
                     continue
                 }
             };
@@ -767,208 +2450,1139 @@ pub fn bind_syscalls(peripherals: SyscallPeripherals, peripherals_config: &Perip
         res
     };
 
-    let peripheral_handles = RefCell::new(PeripheralHandles {
-        digital_ins, digital_outs, motor_groups, hcsr04s, max30205s, is31fl3741s, bmp388s,
-        lis3dhs, veml7700s,
-    });
+    let st7789s = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.st7789s.len());
 
-    let config = Config::<C, _> {
-        request: Some(Rc::new(move |_, key, request, _| match &request {
-            Request::Syscall { name, args } => {
-                let (peripheral_type, peripheral, function) = {
-                    let mut tokens = name.split('.');
-                    match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
-                        (Some(a), Some(b), Some(c), None) => (a, b, c),
-                        _ => return RequestStatus::UseDefault { key, request },
-                    }
+        for entry in peripherals_config.st7789s.iter() {
+            if let Err(error) = entry.check() {
+                errors.push(InitError { context: format!("st7789s {}", entry.name), error });
+                continue
+            }
+            let spi = match spi.clone() {
+                Some(x) => x,
+                None => {
+                    errors.push(InitError { context: format!("st7789s {}", entry.name), error: PeripheralError::SpiNotConfigured });
+                    continue
+                }
+            };
+            let cs = match pins.take_convert(entry.gpio_cs, AnyPin::try_into_output) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("st7789s {} gpio_cs", entry.name), error: e });
+                    continue
+                }
+            };
+            let dc = match pins.take_convert(entry.gpio_dc, AnyPin::try_into_output).and_then(|x| PinDriver::output(x).map_err(Into::into)) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("st7789s {} gpio_dc", entry.name), error: e });
+                    continue
+                }
+            };
+            let rst = match entry.gpio_rst.map(|x| pins.take_convert(x, AnyPin::try_into_output).and_then(|x| PinDriver::output(x).map_err(Into::into))).transpose() {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("st7789s {} gpio_rst", entry.name), error: e });
+                    continue
+                }
+            };
+            let device = match SpiDeviceDriver::new(spi, Some(cs), &SpiConfig::new().baudrate(ST7789_SPI_FREQUENCY_HZ.Hz())) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("st7789s {}", entry.name), error: e.into() });
+                    continue
+                }
+            };
+            let display = match St7789Controller::new(device, dc, rst, entry.width, entry.height, [entry.x_offset, entry.y_offset]) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("st7789s {}", entry.name), error: PeripheralError::Other { cause: format!("{e:?}") } });
+                    continue
+                }
+            };
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("st7789s {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            res.insert(entry.name.clone(), display);
+            menu_content.push(menu_entries!("ST7789", entry.name => "setColor", "clear", "drawText", "drawLine", "drawRect", "showImage", "flush"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "ST7789".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    let sd_cards = {
+        let mut res = BTreeMap::new();
+        let mut menu_content = Vec::with_capacity(peripherals_config.sd_cards.len());
+
+        for entry in peripherals_config.sd_cards.iter() {
+            if res.contains_key(&entry.name) {
+                errors.push(InitError { context: format!("sd_cards {}", entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
+                continue
+            }
+            let spi = match spi.clone() {
+                Some(x) => x,
+                None => {
+                    errors.push(InitError { context: format!("sd_cards {}", entry.name), error: PeripheralError::SpiNotConfigured });
+                    continue
+                }
+            };
+            let cs = match pins.take_convert(entry.gpio_cs, AnyPin::try_into_output) {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("sd_cards {} gpio_cs", entry.name), error: e });
+                    continue
+                }
+            };
+            let mount_point = format!("/sd{}", res.len());
+            let mount = SdSpiHostDriver::new(spi, Some(cs), None::<AnyInputPin>, None::<AnyInputPin>, None::<AnyInputPin>, None)
+                .and_then(|x| SdCardDriver::new_spi(x, &SdCardConfiguration::new()))
+                .and_then(|x| Fatfs::new_sdcard(res.len() as u8, x))
+                .and_then(|x| MountedFatfs::mount(x, &mount_point, SD_CARD_MAX_OPEN_FILES));
+            let mount = match mount {
+                Ok(x) => x,
+                Err(e) => {
+                    errors.push(InitError { context: format!("sd_cards {}", entry.name), error: e.into() });
+                    continue
+                }
+            };
+            res.insert(entry.name.clone(), SdCardController { root: vfs_path(&mount_point), _mount: mount });
+            menu_content.push(menu_entries!("SDCard", entry.name => "readFile", "writeFile", "appendFile", "listFiles", "deleteFile"));
+        }
+        if !menu_content.is_empty() {
+            syscalls.push(SyscallMenu::Submenu { label: "SDCard".into(), content: menu_content });
+        }
+
+        res
+    };
+
+    if !i2c_buses.is_empty() {
+        syscalls.push(SyscallMenu::Submenu { label: "I2C".into(), content: i2c_buses.keys().map(|x| menu_entries!("I2C", x => "scan")).collect() });
+    }
+    let i2c_scanner = I2cScanner { buses: i2c_buses.clone() };
+
+    let peripheral_handles = PeripheralHandles {
+        i2c_buses, digital_ins, digital_outs, analog_ins, motor_groups, servos, pwm_outs, buzzers, pwms, neopixels, hcsr04s, max30205s, is31fl3741s, ssd1306s, bmp388s,
+        lis3dhs, veml7700s, st7789s, sd_cards,
+    };
+
+    (peripheral_handles, sound_buzzer, syscalls, errors, i2c_scanner)
+}
+
+/// Handles the peripheral syscalls, which have names of the form `Type.peripheral.function`.
+/// This is kept separate from the [`Config`] closure so that it can be driven without a running process.
+fn handle_request<'gc>(peripheral_handles: &RefCell<PeripheralHandles>, key: AsyncKey<Result<SimpleValue, CompactString>>, request: Request<'gc, C, EspSystem<C>>) -> RequestStatus<'gc, C, EspSystem<C>> {
+    match &request {
+        Request::Syscall { name, args } => {
+            let (peripheral_type, peripheral, function) = {
+                let mut tokens = name.split('.');
+                match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+                    (Some(a), Some(b), Some(c), None) => (a, b, c),
+                    _ => return RequestStatus::UseDefault { key, request },
+                }
+            };
+
+            macro_rules! unknown {
+                ($id:ident) => { key.complete(Err(format_compact!(concat!("unknown {} ", stringify!($id), ": {:?}"), peripheral_type, $id))) }
+            }
+            macro_rules! ok {
+                () => { key.complete(Ok("OK".to_owned().into())); }
+            }
+
+            macro_rules! count_expected {
+                () => { 0usize };
+                ($_:ident $($rest:tt)*) => { 1usize + count_expected!($($rest)*) };
+                ([$_:ident ; $n:expr] $($rest:tt)*) => { $n + count_expected!($($rest)*) };
+            }
+            macro_rules! parse_args_inner {
+                (($index:expr) $first:ident $($rest:tt)+) => {
+                    (parse_args_inner!(($index) $first), parse_args_inner!(($index + 1usize) $($rest)+))
                 };
+                (($index:expr) [$first:ident ; $n:expr]) => {{
+                    let index = $index;
+                    let n = $n;
+                    let mut res = Vec::with_capacity(n);
+                    for i in 0..n {
+                        res.push(parse_args_inner!((index + i) $first));
+                    }
+                    res
+                }};
+                (($index:expr) bool) => {{
+                    let index = $index;
+                    match args[index].as_bool() {
+                        Ok(x) => x,
+                        Err(e) => {
+                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a bool for arg {}, but got {:?}", index + 1, e.got)));
+                            return RequestStatus::Handled;
+                        }
+                    }
+                }};
+                (($index:expr) list) => {{
+                    let index = $index;
+                    match args[index].as_list() {
+                        Ok(x) => x,
+                        Err(e) => {
+                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a list for arg {}, but got {:?}", index + 1, e.got)));
+                            return RequestStatus::Handled;
+                        }
+                    }
+                }};
+                (($index:expr) f64) => {{
+                    let index = $index;
+                    match args[index].as_number() {
+                        Ok(x) => x.get(),
+                        Err(e) => {
+                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a number for arg {}, but got {:?}", index + 1, e.got)));
+                            return RequestStatus::Handled;
+                        }
+                    }
+                }};
+                (($index:expr) u8) => {{
+                    let raw = parse_args_inner!(($index) f64);
+                    let cvt = raw as u8;
+                    if cvt as f64 != raw {
+                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected an integer in [0, 255] for arg {}, but got {raw}", $index + 1)));
+                        return RequestStatus::Handled;
+                    }
+                    cvt
+                }};
+                (($index:expr) u16) => {{
+                    let raw = parse_args_inner!(($index) f64);
+                    let cvt = raw as u16;
+                    if cvt as f64 != raw {
+                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected an integer in [0, 65535] for arg {}, but got {raw}", $index + 1)));
+                        return RequestStatus::Handled;
+                    }
+                    cvt
+                }};
+                (($index:expr) string) => {{
+                    let index = $index;
+                    match args[index].as_string() {
+                        Ok(x) => x,
+                        Err(e) => {
+                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a string for arg {}, but got {:?}", index + 1, e.got)));
+                            return RequestStatus::Handled;
+                        }
+                    }
+                }};
+                (($index:expr) image) => {{
+                    let index = $index;
+                    match &args[index] {
+                        Value::Image(x) => x.clone(),
+                        x => {
+                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected an image for arg {}, but got {:?}", index + 1, x.get_type())));
+                            return RequestStatus::Handled;
+                        }
+                    }
+                }};
+                (($_:expr)) => { () };
+            }
+            macro_rules! parse_args {
+                ($($t:tt)*) => {{
+                    let expected = count_expected!($($t)*);
+                    if args.len() != expected {
+                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected {expected} args, but got {}", args.len())));
+                        return RequestStatus::Handled;
+                    }
+                    parse_args_inner!((0usize) $($t)*)
+                }};
+            }
 
-                macro_rules! unknown {
-                    ($id:ident) => { key.complete(Err(format_compact!(concat!("unknown {} ", stringify!($id), ": {:?}"), peripheral_type, $id))) }
-                }
-                macro_rules! ok {
-                    () => { key.complete(Ok("OK".to_owned().into())); }
-                }
-
-                macro_rules! count_expected {
-                    () => { 0usize };
-                    ($_:ident $($rest:tt)*) => { 1usize + count_expected!($($rest)*) };
-                    ([$_:ident ; $n:expr] $($rest:tt)*) => { $n + count_expected!($($rest)*) };
-                }
-                macro_rules! parse_args_inner {
-                    (($index:expr) $first:ident $($rest:tt)+) => {
-                        (parse_args_inner!(($index) $first), parse_args_inner!(($index + 1usize) $($rest)+))
-                    };
-                    (($index:expr) [$first:ident ; $n:expr]) => {{
-                        let index = $index;
-                        let n = $n;
-                        let mut res = Vec::with_capacity(n);
-                        for i in 0..n {
-                            res.push(parse_args_inner!((index + i) $first));
-                        }
-                        res
-                    }};
-                    (($index:expr) bool) => {{
-                        let index = $index;
-                        match args[index].as_bool() {
-                            Ok(x) => x,
-                            Err(e) => {
-                                key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a bool for arg {}, but got {:?}", index + 1, e.got)));
-                                return RequestStatus::Handled;
+            let mut peripheral_handles = peripheral_handles.borrow_mut();
+            match peripheral_type {
+                "DigitalIn" => match peripheral_handles.digital_ins.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "get" => {
+                            parse_args!();
+                            match handle.get_value() {
+                                Ok(x) => key.complete(Ok(x.into())),
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
                             }
                         }
-                    }};
-                    (($index:expr) f64) => {{
-                        let index = $index;
-                        match args[index].as_number() {
-                            Ok(x) => x.get(),
-                            Err(e) => {
-                                key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a number for arg {}, but got {:?}", index + 1, e.got)));
-                                return RequestStatus::Handled;
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "DigitalOut" => match peripheral_handles.digital_outs.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "set" => {
+                            let value = parse_args!(bool);
+                            match handle.set_value(value) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
                             }
                         }
-                    }};
-                    (($index:expr) u8) => {{
-                        let raw = parse_args_inner!(($index) f64);
-                        let cvt = raw as u8;
-                        if cvt as f64 != raw {
-                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected an integer in [0, 255] for arg {}, but got {raw}", $index + 1)));
-                            return RequestStatus::Handled;
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "AnalogIn" => match peripheral_handles.analog_ins.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getVoltage" => {
+                            parse_args!();
+                            match handle.get_voltage() {
+                                Ok(x) => key.complete(Ok(Number::new(x).unwrap().into())),
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
                         }
-                        cvt
-                    }};
-                    (($_:expr)) => { () };
+                        "getRaw" => {
+                            parse_args!();
+                            match handle.get_raw() {
+                                Ok(x) => key.complete(Ok(Number::new(x).unwrap().into())),
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
                 }
-                macro_rules! parse_args {
-                    ($($t:tt)*) => {{
-                        let expected = count_expected!($($t)*);
-                        if args.len() != expected {
-                            key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected {expected} args, but got {}", args.len())));
-                            return RequestStatus::Handled;
+                "Motor" => match peripheral_handles.motor_groups.get(peripheral) {
+                    Some(handle) => match function {
+                        "setPower" => {
+                            let powers = parse_args!([f64; handle.len()]);
+                            match iter::zip(handle, powers).try_for_each(|(motor, power)| motor.borrow_mut().set_power(power)) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
                         }
-                        parse_args_inner!((0usize) $($t)*)
-                    }};
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
                 }
-
-                let mut peripheral_handles = peripheral_handles.borrow_mut();
-                match peripheral_type {
-                    "DigitalIn" => match peripheral_handles.digital_ins.get(peripheral) {
-                        Some(handle) => match function {
-                            "get" => {
-                                parse_args!();
-                                key.complete(Ok(handle.get_value().into()));
+                "Servo" => match peripheral_handles.servos.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "setAngle" => {
+                            let angle = parse_args!(f64);
+                            match handle.set_angle(angle) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        "release" => {
+                            parse_args!();
+                            match handle.release() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
                             }
-                            _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
+                        _ => unknown!(function),
                     }
-                    "DigitalOut" => match peripheral_handles.digital_outs.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "set" => {
-                                let value = parse_args!(bool);
-                                handle.set_value(value).unwrap();
-                                ok!();
+                    None => unknown!(peripheral),
+                }
+                "PWM" => match peripheral_handles.pwm_outs.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "setDuty" => {
+                            let percent = parse_args!(f64);
+                            match handle.set_duty(percent) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        "setFrequency" => {
+                            let frequency = parse_args!(f64);
+                            let timer = handle.timer;
+                            match peripheral_handles.pwms.set_frequency(timer, frequency.clamp(0.0, u32::MAX as f64) as u32) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
                             }
-                            _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
+                        _ => unknown!(function),
                     }
-                    "Motor" => match peripheral_handles.motor_groups.get(peripheral) {
-                        Some(handle) => match function {
-                            "setPower" => {
-                                let powers = parse_args!([f64; handle.len()]);
-                                for (motor, power) in iter::zip(handle, powers) {
-                                    motor.borrow_mut().set_power(power).unwrap();
+                    None => unknown!(peripheral),
+                }
+                "Buzzer" => match peripheral_handles.buzzers.get(peripheral) {
+                    Some(handle) => match function {
+                        "playTone" => {
+                            let (frequency, duration) = parse_args!(f64 f64);
+                            match Tone::new(frequency, duration / 1000.0) {
+                                Some(tone) => {
+                                    handle.play(vec![tone], None);
+                                    ok!();
                                 }
-                                ok!();
+                                None => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: invalid duration {duration}"))),
                             }
-                            _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
-                    }
-                    "HCSR04" => match peripheral_handles.hcsr04s.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "getDistance" => {
-                                parse_args!();
-                                key.complete(Ok(Number::new(handle.get_distance().unwrap()).unwrap().into()));
+                        "playNotes" => {
+                            let notes = parse_args!(list);
+                            let mut tones = Vec::with_capacity(notes.borrow().len());
+                            for (i, note) in notes.borrow().iter().enumerate() {
+                                match Tone::parse(note) {
+                                    Some(x) => tones.push(x),
+                                    None => {
+                                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a list of [note, duration] pairs, but item {} was invalid", i + 1)));
+                                        return RequestStatus::Handled;
+                                    }
+                                }
                             }
-                            _ => unknown!(function),
+                            handle.play(tones, None);
+                            ok!();
                         }
-                        None => unknown!(peripheral),
+                        "stop" => {
+                            parse_args!();
+                            handle.stop();
+                            ok!();
+                        }
+                        _ => unknown!(function),
                     }
-                    "MAX30205" => match peripheral_handles.max30205s.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "getTemperature" => {
-                                parse_args!();
-                                key.complete(Ok(Number::new(handle.get_temperature().unwrap()).unwrap().into()));
+                    None => unknown!(peripheral),
+                }
+                "NeoPixel" => match peripheral_handles.neopixels.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "setPixel" => {
+                            let (index, (r, (g, b))) = parse_args!(u16 u8 u8 u8);
+                            match handle.pixels.get_mut(index as usize) {
+                                Some(pixel) => *pixel = [r, g, b],
+                                None => {
+                                    key.complete(Err(format_compact!("pixel index {index} is out of bounds")));
+                                    return RequestStatus::Handled;
+                                }
                             }
-                            _ => unknown!(function),
+                            ok!();
+                        }
+                        "fill" => {
+                            let (r, (g, b)) = parse_args!(u8 u8 u8);
+                            handle.pixels.fill([r, g, b]);
+                            ok!();
                         }
-                        None => unknown!(peripheral),
+                        "setPixels" => {
+                            let colors = parse_args!(list);
+                            let colors = colors.borrow();
+                            if colors.len() > handle.pixels.len() {
+                                key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected at most {} colors, but got {}", handle.pixels.len(), colors.len())));
+                                return RequestStatus::Handled;
+                            }
+                            let mut pixels = Vec::with_capacity(colors.len());
+                            for (i, color) in colors.iter().enumerate() {
+                                match parse_color(color) {
+                                    Some(x) => pixels.push(x),
+                                    None => {
+                                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected a list of [r, g, b] colors, but item {} was invalid", i + 1)));
+                                        return RequestStatus::Handled;
+                                    }
+                                }
+                            }
+                            handle.pixels[..pixels.len()].copy_from_slice(&pixels);
+                            ok!();
+                        }
+                        "setBrightness" => {
+                            let percent = parse_args!(f64);
+                            handle.set_brightness(percent);
+                            ok!();
+                        }
+                        "show" => {
+                            parse_args!();
+                            match handle.show() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        _ => unknown!(function),
                     }
-                    "IS31FL3741" => match peripheral_handles.is31fl3741s.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "setPixel" => {
-                                let (x, (y, (r, (g, b)))) = parse_args!(u8 u8 u8 u8 u8);
-                                if x >= 13 || y >= 9 {
-                                    key.complete(Err(format_compact!("pixel position ({x}, {y}) is out of bounds")));
-                                    return RequestStatus::Handled;
+                    None => unknown!(peripheral),
+                }
+                "HCSR04" => match peripheral_handles.hcsr04s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getDistance" => {
+                            parse_args!();
+                            key.complete(Ok(Number::new(handle.get_distance().unwrap()).unwrap().into()));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "MAX30205" => match peripheral_handles.max30205s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getTemperature" => {
+                            parse_args!();
+                            key.complete(Ok(Number::new(handle.get_temperature().unwrap()).unwrap().into()));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "IS31FL3741" => match peripheral_handles.is31fl3741s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "setPixel" => {
+                            let (x, (y, (r, (g, b)))) = parse_args!(u8 u8 u8 u8 u8);
+                            if x >= IS31FL3741_WIDTH || y >= IS31FL3741_HEIGHT {
+                                key.complete(Err(format_compact!("pixel position ({x}, {y}) is out of bounds")));
+                                return RequestStatus::Handled;
+                            }
+                            match handle.set_pixel(x, y, [r, g, b]) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        "setPixels" => {
+                            let rows = parse_args!(list);
+                            let rows = rows.borrow();
+                            let mut pixels = Vec::with_capacity(handle.pixels.len());
+                            for row in rows.iter() {
+                                let row = row.as_list().ok();
+                                match row.as_ref().map(|x| x.borrow()) {
+                                    Some(row) if row.len() == IS31FL3741_WIDTH as usize => {
+                                        for color in row.iter() {
+                                            match parse_color(color) {
+                                                Some(x) => pixels.push(x),
+                                                None => {
+                                                    key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected [r, g, b] colors, but row {} had an invalid color", pixels.len() / IS31FL3741_WIDTH as usize + 1)));
+                                                    return RequestStatus::Handled;
+                                                }
+                                            }
+                                        }
+                                    }
+                                    _ => {
+                                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected {IS31FL3741_HEIGHT} rows of {IS31FL3741_WIDTH} colors")));
+                                        return RequestStatus::Handled;
+                                    }
                                 }
-                                handle.pixel_rgb(x, y, r, g, b).unwrap();
-                                ok!();
                             }
-                            _ => unknown!(function),
+                            if pixels.len() != handle.pixels.len() {
+                                key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} expected {IS31FL3741_HEIGHT} rows of {IS31FL3741_WIDTH} colors")));
+                                return RequestStatus::Handled;
+                            }
+                            handle.pixels = pixels;
+                            match handle.show() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
                         }
-                        None => unknown!(peripheral),
+                        "showImage" => {
+                            let image = parse_args!(image);
+                            match crate::image::decode_rgb(&image.content, IS31FL3741_WIDTH as usize, IS31FL3741_HEIGHT as usize) {
+                                Ok(pixels) => {
+                                    handle.pixels = pixels;
+                                    match handle.show() {
+                                        Ok(()) => { ok!(); }
+                                        Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                                    }
+                                }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed to load image: {e}"))),
+                            }
+                        }
+                        "fill" => {
+                            let (r, (g, b)) = parse_args!(u8 u8 u8);
+                            handle.pixels.fill([r, g, b]);
+                            match handle.show() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        "clear" => {
+                            parse_args!();
+                            handle.pixels.fill([0; 3]);
+                            match handle.show() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        "setBrightness" => {
+                            let percent = parse_args!(f64);
+                            match handle.set_brightness(percent) {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        _ => unknown!(function),
                     }
-                    "BMP388" => match peripheral_handles.bmp388s.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "getPressure" => {
-                                parse_args!();
-                                key.complete(Ok(Number::new(handle.sensor_values().unwrap().pressure).unwrap().into()));
+                    None => unknown!(peripheral),
+                }
+                "SSD1306" => match peripheral_handles.ssd1306s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "clear" => {
+                            parse_args!();
+                            handle.clear();
+                            ok!();
+                        }
+                        "drawText" => {
+                            let (x, (y, text)) = parse_args!(f64 f64 string);
+                            let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+                            Text::with_baseline(&text, Point::new(x.round() as i32, y.round() as i32), style, Baseline::Top).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "drawLine" => {
+                            let (x1, (y1, (x2, y2))) = parse_args!(f64 f64 f64 f64);
+                            let (start, end) = (Point::new(x1.round() as i32, y1.round() as i32), Point::new(x2.round() as i32, y2.round() as i32));
+                            Line::new(start, end).into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1)).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "drawRect" => {
+                            let (x, (y, (width, (height, filled)))) = parse_args!(f64 f64 f64 f64 bool);
+                            let style = if filled { PrimitiveStyle::with_fill(BinaryColor::On) } else { PrimitiveStyle::with_stroke(BinaryColor::On, 1) };
+                            let size = Size::new(width.round().max(0.0) as u32, height.round().max(0.0) as u32);
+                            Rectangle::new(Point::new(x.round() as i32, y.round() as i32), size).into_styled(style).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "showImage" => {
+                            let image = parse_args!(image);
+                            match crate::image::decode_rgb(&image.content, OLED_WIDTH, handle.height) {
+                                Ok(pixels) => {
+                                    handle.show_image(&crate::image::dither_monochrome(&pixels, OLED_WIDTH));
+                                    ok!();
+                                }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed to load image: {e}"))),
                             }
-                            "getTemperature" => {
-                                parse_args!();
-                                key.complete(Ok(Number::new(handle.sensor_values().unwrap().temperature).unwrap().into()));
+                        }
+                        "flush" => {
+                            parse_args!();
+                            match handle.flush() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
                             }
-                            _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
+                        _ => unknown!(function),
                     }
-                    "LIS3DH" => match peripheral_handles.lis3dhs.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "getAcceleration" => {
-                                parse_args!();
-                                let vals = lis3dh::accelerometer::Accelerometer::accel_norm(handle).unwrap();
-                                key.complete(Ok(SimpleValue::List(vec![
-                                    Number::new(vals.x as f64).unwrap().into(),
-                                    Number::new(vals.y as f64).unwrap().into(),
-                                    Number::new(vals.z as f64).unwrap().into(),
-                                ])));
+                    None => unknown!(peripheral),
+                }
+                "BMP388" => match peripheral_handles.bmp388s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getPressure" => {
+                            parse_args!();
+                            key.complete(Ok(Number::new(handle.sensor_values().unwrap().pressure).unwrap().into()));
+                        }
+                        "getTemperature" => {
+                            parse_args!();
+                            key.complete(Ok(Number::new(handle.sensor_values().unwrap().temperature).unwrap().into()));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "LIS3DH" => match peripheral_handles.lis3dhs.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getAcceleration" => {
+                            parse_args!();
+                            let vals = lis3dh::accelerometer::Accelerometer::accel_norm(handle).unwrap();
+                            key.complete(Ok(SimpleValue::List(vec![
+                                Number::new(vals.x as f64).unwrap().into(),
+                                Number::new(vals.y as f64).unwrap().into(),
+                                Number::new(vals.z as f64).unwrap().into(),
+                            ])));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "I2C" => match peripheral_handles.i2c_buses.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "scan" => {
+                            parse_args!();
+                            let res = scan_i2c_bus(handle).into_iter().map(|(address, devices)| {
+                                let devices = SimpleValue::List(devices.into_iter().map(|x| SimpleValue::String(x.into())).collect());
+                                SimpleValue::List(vec![Number::new(address as f64).unwrap().into(), devices])
+                            }).collect();
+                            key.complete(Ok(SimpleValue::List(res)));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "VEML7700" => match peripheral_handles.veml7700s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "getLight" => {
+                            parse_args!();
+                            key.complete(Ok(Number::new(handle.read_lux().unwrap() as f64).unwrap().into()));
+                        }
+                        _ => unknown!(function),
+                    }
+                    None => unknown!(peripheral),
+                }
+                "ST7789" => match peripheral_handles.st7789s.get_mut(peripheral) {
+                    Some(handle) => match function {
+                        "setColor" => {
+                            let (r, (g, b)) = parse_args!(u8 u8 u8);
+                            handle.color = Rgb565::new(r >> 3, g >> 2, b >> 3);
+                            ok!();
+                        }
+                        "clear" => {
+                            parse_args!();
+                            handle.buffer.fill(0);
+                            ok!();
+                        }
+                        "drawText" => {
+                            let (x, (y, text)) = parse_args!(f64 f64 string);
+                            let style = MonoTextStyle::new(&FONT_10X20, handle.color);
+                            Text::with_baseline(&text, Point::new(x.round() as i32, y.round() as i32), style, Baseline::Top).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "drawLine" => {
+                            let (x1, (y1, (x2, y2))) = parse_args!(f64 f64 f64 f64);
+                            let (start, end) = (Point::new(x1.round() as i32, y1.round() as i32), Point::new(x2.round() as i32, y2.round() as i32));
+                            Line::new(start, end).into_styled(PrimitiveStyle::with_stroke(handle.color, 1)).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "drawRect" => {
+                            let (x, (y, (width, (height, filled)))) = parse_args!(f64 f64 f64 f64 bool);
+                            let style = if filled { PrimitiveStyle::with_fill(handle.color) } else { PrimitiveStyle::with_stroke(handle.color, 1) };
+                            let size = Size::new(width.round().max(0.0) as u32, height.round().max(0.0) as u32);
+                            Rectangle::new(Point::new(x.round() as i32, y.round() as i32), size).into_styled(style).draw(handle).unwrap();
+                            ok!();
+                        }
+                        "showImage" => {
+                            let image = parse_args!(image);
+                            match crate::image::decode_rgb(&image.content, handle.width as usize, handle.height as usize) {
+                                Ok(pixels) => {
+                                    handle.show_image(&pixels);
+                                    ok!();
+                                }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed to load image: {e}"))),
                             }
-                            _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
+                        "flush" => {
+                            parse_args!();
+                            match handle.flush() {
+                                Ok(()) => { ok!(); }
+                                Err(e) => key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e:?}"))),
+                            }
+                        }
+                        _ => unknown!(function),
                     }
-                    "VEML7700" => match peripheral_handles.veml7700s.get_mut(peripheral) {
-                        Some(handle) => match function {
-                            "getLight" => {
-                                parse_args!();
-                                key.complete(Ok(Number::new(handle.read_lux().unwrap() as f64).unwrap().into()));
+                    None => unknown!(peripheral),
+                }
+                "SDCard" => match peripheral_handles.sd_cards.get_mut(peripheral) {
+                    Some(handle) => {
+                        macro_rules! parse_path {
+                            ($path:expr) => {{
+                                let path = $path;
+                                match handle.path(&path) {
+                                    Some(x) => x,
+                                    None => {
+                                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} path must not leave the sd card, but got {:?}", &*path)));
+                                        return RequestStatus::Handled;
+                                    }
+                                }
+                            }};
+                        }
+                        macro_rules! io_result {
+                            ($res:expr) => {{
+                                match $res {
+                                    Ok(x) => x,
+                                    Err(e) => {
+                                        key.complete(Err(format_compact!("{peripheral_type}.{peripheral}.{function} failed: {e}")));
+                                        return RequestStatus::Handled;
+                                    }
+                                }
+                            }};
+                        }
+
+                        match function {
+                            "readFile" => {
+                                let path = parse_path!(parse_args!(string));
+                                let content = io_result!(fs::read_to_string(path));
+                                key.complete(Ok(content.into()));
+                            }
+                            "writeFile" => {
+                                let (path, content) = parse_args!(string string);
+                                let path = parse_path!(path);
+                                io_result!(fs::write(path, content.as_bytes()));
+                                ok!();
+                            }
+                            "appendFile" => {
+                                let (path, content) = parse_args!(string string);
+                                let path = parse_path!(path);
+                                io_result!(fs::OpenOptions::new().create(true).append(true).open(path).and_then(|mut f| f.write_all(content.as_bytes())));
+                                ok!();
+                            }
+                            "listFiles" => {
+                                let path = parse_path!(parse_args!(string));
+                                let mut names = Vec::new();
+                                for entry in io_result!(fs::read_dir(path)) {
+                                    let entry = io_result!(entry);
+                                    let mut name = entry.file_name().to_string_lossy().into_owned();
+                                    if io_result!(entry.file_type()).is_dir() {
+                                        name.push('/');
+                                    }
+                                    names.push(name);
+                                }
+                                names.sort();
+                                key.complete(Ok(SimpleValue::List(names.into_iter().map(Into::into).collect())));
+                            }
+                            "deleteFile" => {
+                                let path = parse_path!(parse_args!(string));
+                                io_result!(fs::remove_file(path));
+                                ok!();
                             }
                             _ => unknown!(function),
                         }
-                        None => unknown!(peripheral),
                     }
-                    _ => return RequestStatus::UseDefault { key, request },
+                    None => unknown!(peripheral),
+                }
+                _ => return RequestStatus::UseDefault { key, request },
+            }
+
+            RequestStatus::Handled
+        }
+        _ => RequestStatus::UseDefault { key, request },
+    }
+}
+
+// -----------------------------------------------------------------
+
+/// A problem with a peripherals config, as found by [`validate_config`].
+#[derive(Debug, Serialize)]
+pub struct ConfigError {
+    /// The entry (and field, if relevant) with the problem, e.g., `"servos arm gpio"`.
+    pub context: String,
+    pub error: String,
+}
+
+/// Checks a peripherals config without touching any hardware, which catches malformed json, unknown fields,
+/// pin conflicts and missing pin capabilities, running out of pwm/rmt channels or i2c controllers, duplicate names, and invalid settings.
+/// Failures that depend on the connected devices (e.g., a sensor not responding) are only found by [`bind_syscalls`].
+pub fn validate_config(content: &str) -> Vec<ConfigError> {
+    let config = match netsblox_vm::json::parse_json::<PeripheralsConfig>(content) {
+        Ok(x) => x,
+        Err(e) => return vec![ConfigError { context: "config".into(), error: format!("{e:?}") }],
+    };
+
+    let mut errors = vec![];
+    let mut pins = GpioManager::new_dry_run();
+    let mut pwms = PwmBudget::new();
+    let mut rmt_channels = 4;
+    let mut i2c_controllers = 2;
+
+    macro_rules! check {
+        ($res:expr, $($context:tt)*) => {
+            if let Err(error) = $res {
+                errors.push(InitError { context: format!($($context)*), error });
+            }
+        }
+    }
+    macro_rules! check_names {
+        ($kind:expr => $($entries:expr),+) => {{
+            let mut names = BTreeSet::new();
+            $(for entry in $entries.iter() {
+                if !names.insert(entry.name.as_str()) {
+                    errors.push(InitError { context: format!("{} {}", $kind, entry.name), error: PeripheralError::NameAlreadyTaken { name: entry.name.clone() } });
                 }
+            })+
+            names
+        }};
+    }
+
+    let i2c_buses = check_names!("I2C" => config.i2c, config.i2c_buses);
+    for entry in config.i2c.iter().chain(config.i2c_buses.iter()) {
+        check!(pins.take_convert(entry.gpio_sda, AnyPin::check_input_output), "I2C {} gpio_sda", entry.name);
+        check!(pins.take_convert(entry.gpio_scl, AnyPin::check_input_output), "I2C {} gpio_scl", entry.name);
+        match i2c_controllers {
+            0 => errors.push(InitError { context: format!("I2C {}", entry.name), error: PeripheralError::I2cOutOfControllers }),
+            _ => i2c_controllers -= 1,
+        }
+    }
+    if let Some(spi) = &config.spi {
+        check!(pins.take_convert(spi.gpio_sclk, AnyPin::check_output), "SPI gpio_sclk");
+        check!(pins.take_convert(spi.gpio_mosi, AnyPin::check_output), "SPI gpio_mosi");
+        check!(spi.gpio_miso.map(|x| pins.take_convert(x, AnyPin::check_input)).transpose(), "SPI gpio_miso");
+    }
+
+    check_names!("digital_ins" => config.digital_ins);
+    for entry in config.digital_ins.iter() {
+        check!(entry.check(), "digital_ins {}", entry.name);
+        check!(pins.take_convert(entry.gpio, AnyPin::check_input_output), "digital_ins {} gpio", entry.name);
+    }
+    check_names!("digital_outs" => config.digital_outs);
+    for entry in config.digital_outs.iter() {
+        check!(entry.check(), "digital_outs {}", entry.name);
+        check!(pins.take_convert(entry.gpio, AnyPin::check_input_output), "digital_outs {} gpio", entry.name);
+    }
+    check_names!("analog_ins" => config.analog_ins);
+    for entry in config.analog_ins.iter() {
+        check!(entry.check(), "analog_ins {}", entry.name);
+        check!(pins.take_convert(entry.gpio, AnyPin::try_into_analog), "analog_ins {} gpio", entry.name);
+    }
 
-                RequestStatus::Handled
+    check_names!("motors" => config.motors, config.motor_groups);
+    for entry in config.motors.iter() {
+        check!(pins.take_convert(entry.gpio_pos, AnyPin::check_output).and_then(|_| pwms.take(PwmTimerConfig::motor())), "motors {} gpio_pos", entry.name);
+        check!(pins.take_convert(entry.gpio_neg, AnyPin::check_output).and_then(|_| pwms.take(PwmTimerConfig::motor())), "motors {} gpio_neg", entry.name);
+    }
+    for entry in config.motor_groups.iter() {
+        for name in entry.motors.iter() {
+            if !config.motors.iter().any(|x| x.name == *name) {
+                errors.push(InitError { context: format!("motor_groups {}", entry.name), error: PeripheralError::NameUnknown { name: name.clone() } });
             }
-            _ => RequestStatus::UseDefault { key, request },
-        })),
-        command: None,
+        }
+    }
+    check_names!("servos" => config.servos);
+    for entry in config.servos.iter() {
+        check!(entry.check(), "servos {}", entry.name);
+        check!(pins.take_convert(entry.gpio, AnyPin::check_output).and_then(|_| pwms.take(PwmTimerConfig::servo())), "servos {} gpio", entry.name);
+    }
+    check_names!("pwm_outs" => config.pwm_outs);
+    for entry in config.pwm_outs.iter() {
+        let timer_config = PwmTimerConfig { usage: PwmUsage::Output, frequency: entry.frequency, resolution_bits: entry.resolution_bits };
+        check!(pins.take_convert(entry.gpio, AnyPin::check_output).and_then(|_| pwms.take(timer_config)), "pwm_outs {} gpio", entry.name);
+    }
+    check_names!("buzzers" => config.buzzers);
+    for entry in config.buzzers.iter() {
+        check!(pins.take_convert(entry.gpio, AnyPin::check_output).and_then(|_| pwms.take(PwmTimerConfig::buzzer())), "buzzers {} gpio", entry.name);
+    }
+    check_names!("neopixels" => config.neopixels);
+    for entry in config.neopixels.iter() {
+        check!(entry.check(), "neopixels {}", entry.name);
+        let rmt = match rmt_channels {
+            0 => Err(PeripheralError::RmtOutOfChannels),
+            _ => {
+                rmt_channels -= 1;
+                Ok(())
+            }
+        };
+        check!(pins.take_convert(entry.gpio, AnyPin::check_output).and(rmt), "neopixels {} gpio", entry.name);
+    }
+    check_names!("hcsr04s" => config.hcsr04s);
+    for entry in config.hcsr04s.iter() {
+        check!(pins.take_convert(entry.gpio_trigger, AnyPin::check_output), "hcsr04s {} gpio_trigger", entry.name);
+        check!(pins.take_convert(entry.gpio_echo, AnyPin::check_input), "hcsr04s {} gpio_echo", entry.name);
+    }
+
+    let mut i2c_addrs = BTreeMap::new();
+    let mut check_i2c = |kind: &str, name: &str, bus: &str, addr: u8| -> Result<(), PeripheralError> {
+        if !i2c_buses.contains(bus) {
+            return Err(if bus == DEFAULT_I2C_BUS { PeripheralError::I2cNotConfigured } else { PeripheralError::NameUnknown { name: bus.into() } });
+        }
+        match i2c_addrs.insert((bus.to_owned(), addr), format!("{kind} {name}")) {
+            Some(other) => Err(PeripheralError::Other { cause: format!("i2c address {addr:#04x} on bus {bus} is already used by {other}") }),
+            None => Ok(()),
+        }
     };
+    macro_rules! check_basic_i2c {
+        ($($kind:ident),+) => {$({
+            check_names!(stringify!($kind) => config.$kind);
+            for entry in config.$kind.iter() {
+                check!(check_i2c(stringify!($kind), &entry.name, &entry.i2c_bus, entry.i2c_addr), "{} {}", stringify!($kind), entry.name);
+            }
+        })+}
+    }
+    check_basic_i2c!(max30205s, is31fl3741s, bmp388s, lis3dhs, veml7700s);
+    check_names!("ssd1306s" => config.ssd1306s);
+    for entry in config.ssd1306s.iter() {
+        check!(entry.check(), "ssd1306s {}", entry.name);
+        check!(check_i2c("ssd1306s", &entry.name, &entry.i2c_bus, entry.i2c_addr), "ssd1306s {}", entry.name);
+    }
+
+    let spi = || if config.spi.is_some() { Ok(()) } else { Err(PeripheralError::SpiNotConfigured) };
+    check_names!("st7789s" => config.st7789s);
+    for entry in config.st7789s.iter() {
+        check!(entry.check(), "st7789s {}", entry.name);
+        check!(spi(), "st7789s {}", entry.name);
+        check!(pins.take_convert(entry.gpio_cs, AnyPin::check_output), "st7789s {} gpio_cs", entry.name);
+        check!(pins.take_convert(entry.gpio_dc, AnyPin::check_output), "st7789s {} gpio_dc", entry.name);
+        check!(entry.gpio_rst.map(|x| pins.take_convert(x, AnyPin::check_output)).transpose(), "st7789s {} gpio_rst", entry.name);
+    }
+    check_names!("sd_cards" => config.sd_cards);
+    for entry in config.sd_cards.iter() {
+        check!(spi(), "sd_cards {}", entry.name);
+        check!(pins.take_convert(entry.gpio_cs, AnyPin::check_output), "sd_cards {} gpio_cs", entry.name);
+    }
+
+    errors.into_iter().map(|e| ConfigError { context: e.context, error: format!("{:?}", e.error) }).collect()
+}
+
+/// Generates a JSON Schema for the peripherals config, which is derived from the same types used to parse it.
+pub fn config_schema() -> String {
+    netsblox_vm::json::serde_json::to_string_pretty(&schemars::schema_for!(PeripheralsConfig)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use netsblox_vm::runtime::AsyncResult;
+    use netsblox_vm::gc::gc_arena::{Arena, Rootable};
 
-    (config, syscalls, errors)
+    // the simulated hardware is process-wide, so tests that bind peripherals must not run concurrently
+    static SIM_LOCK: Mutex<()> = Mutex::new(());
+
+    fn peripherals() -> SyscallPeripherals {
+        unsafe { SyscallPeripherals::reclaim() } // the simulated peripherals can always be reclaimed
+    }
+
+    fn init(config: &str) -> (RefCell<PeripheralHandles>, Vec<SyscallMenu>, Vec<InitError>) {
+        let config = netsblox_vm::json::parse_json::<PeripheralsConfig>(config).unwrap();
+        let (messages, _) = channel();
+        let (handles, _, syscalls, errors, _) = init_peripherals(peripherals(), &config, &messages);
+        (RefCell::new(handles), syscalls, errors)
+    }
+
+    /// Performs a syscall, giving `None` if it was not handled (i.e., it would fall back to the default behavior).
+    fn syscall(handles: &RefCell<PeripheralHandles>, name: &str, args: Vec<SimpleValue>) -> Option<Result<SimpleValue, CompactString>> {
+        let arena = Arena::<Rootable![()]>::new(|_| ());
+        arena.mutate(|mc, _| {
+            let key = AsyncKey::new();
+            let request = Request::Syscall { name: name.into(), args: args.into_iter().map(|x| Value::from_simple(mc, x)).collect() };
+            match handle_request(handles, key.clone(), request) {
+                RequestStatus::Handled => match key.poll() {
+                    AsyncResult::Completed(x) => Some(x),
+                    _ => panic!("{name} was handled but not completed"),
+                }
+                RequestStatus::UseDefault { .. } => None,
+            }
+        })
+    }
+
+    fn error_contexts(errors: &[InitError]) -> Vec<&str> {
+        errors.iter().map(|x| x.context.as_str()).collect()
+    }
+
+    #[test]
+    fn test_bind_syscalls_menu() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let config = netsblox_vm::json::parse_json::<PeripheralsConfig>(r#"{
+            "digital_ins": [{ "name": "btn", "gpio": 4, "negated": false }],
+            "digital_outs": [{ "name": "led", "gpio": 5, "negated": false }]
+        }"#).unwrap();
+        let (messages, _) = channel();
+        let (config, syscalls, errors, _) = bind_syscalls(peripherals(), &config, &messages);
+        assert!(config.request.is_some());
+        assert!(errors.is_empty());
+
+        let entries = syscalls.iter().flat_map(|x| match x {
+            SyscallMenu::Submenu { content, .. } => content.iter().flat_map(|x| match x {
+                SyscallMenu::Submenu { content, .. } => content.iter().filter_map(|x| match x {
+                    SyscallMenu::Entry { value, .. } => Some(value.as_str()),
+                    _ => None,
+                }).collect::<Vec<_>>(),
+                _ => vec![],
+            }).collect::<Vec<_>>(),
+            _ => vec![],
+        }).collect::<Vec<_>>();
+        assert_eq!(entries, ["DigitalIn.btn.get", "DigitalOut.led.set"]);
+    }
+
+    #[test]
+    fn test_init_errors() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (handles, _, errors) = init(r#"{
+            "digital_ins": [
+                { "name": "btn", "gpio": 4, "negated": false },
+                { "name": "btn", "gpio": 6, "negated": false },
+                { "name": "other", "gpio": 4, "negated": false },
+                { "name": "bad", "gpio": 100, "negated": false }
+            ],
+            "max30205s": [{ "name": "temp", "i2c_addr": 72 }]
+        }"#);
+        assert_eq!(error_contexts(&errors), ["digital_ins btn", "digital_ins other gpio", "digital_ins bad gpio", "max30205s temp"]);
+        assert!(matches!(errors[0].error, PeripheralError::NameAlreadyTaken { ref name } if name == "btn"));
+        assert!(matches!(errors[1].error, PeripheralError::PinAlreadyTaken { pin: 4 }));
+        assert!(matches!(errors[2].error, PeripheralError::PinUnknown { pin: 100 }));
+        assert!(matches!(errors[3].error, PeripheralError::I2cNotConfigured));
+
+        // the first of the duplicates is still usable
+        assert_eq!(handles.borrow().digital_ins.keys().collect::<Vec<_>>(), ["btn"]);
+    }
+
+    #[test]
+    fn test_syscall_args() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (handles, _, errors) = init(r#"{
+            "digital_ins": [{ "name": "btn", "gpio": 4, "negated": false }],
+            "digital_outs": [{ "name": "led", "gpio": 5, "negated": false }]
+        }"#);
+        assert!(errors.is_empty());
+
+        assert!(matches!(syscall(&handles, "DigitalIn.btn.get", vec![]), Some(Ok(SimpleValue::Bool(false)))));
+        assert!(matches!(syscall(&handles, "DigitalOut.led.set", vec![SimpleValue::Bool(true)]), Some(Ok(SimpleValue::String(x))) if x == "OK"));
+
+        assert!(matches!(syscall(&handles, "DigitalIn.btn.get", vec![SimpleValue::Bool(true)]), Some(Err(e)) if e == "DigitalIn.btn.get expected 0 args, but got 1"));
+        assert!(matches!(syscall(&handles, "DigitalOut.led.set", vec![]), Some(Err(e)) if e == "DigitalOut.led.set expected 1 args, but got 0"));
+        assert!(matches!(syscall(&handles, "DigitalOut.led.set", vec![SimpleValue::List(vec![])]), Some(Err(e)) if e.starts_with("DigitalOut.led.set expected a bool for arg 1")));
+    }
+
+    #[test]
+    fn test_syscall_unknown() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (handles, _, _) = init(r#"{ "digital_ins": [{ "name": "btn", "gpio": 4, "negated": false }] }"#);
+
+        assert!(matches!(syscall(&handles, "DigitalIn.nope.get", vec![]), Some(Err(e)) if e == r#"unknown DigitalIn peripheral: "nope""#));
+        assert!(matches!(syscall(&handles, "DigitalIn.btn.nope", vec![]), Some(Err(e)) if e == r#"unknown DigitalIn function: "nope""#));
+
+        // anything else is left to the default handler
+        assert!(syscall(&handles, "Nope.btn.get", vec![]).is_none());
+        assert!(syscall(&handles, "DigitalIn.btn", vec![]).is_none());
+        assert!(syscall(&handles, "DigitalIn.btn.get.extra", vec![]).is_none());
+    }
+
+    #[test]
+    fn test_syscall_hardware_error() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (handles, _, errors) = init(r#"{ "digital_outs": [{ "name": "led", "gpio": 6, "negated": false }] }"#);
+        assert!(errors.is_empty());
+
+        crate::hal::set_pin_fault(6, true);
+        let res = syscall(&handles, "DigitalOut.led.set", vec![SimpleValue::Bool(true)]);
+        crate::hal::set_pin_fault(6, false);
+        assert!(matches!(res, Some(Err(e)) if e.starts_with("DigitalOut.led.set failed: ")));
+
+        assert!(matches!(syscall(&handles, "DigitalOut.led.set", vec![SimpleValue::Bool(true)]), Some(Ok(SimpleValue::String(x))) if x == "OK"));
+    }
+
+    #[test]
+    fn test_pwm_resolution_bits() {
+        let errors = validate_config(r#"{
+            "pwm_outs": [
+                { "name": "a", "gpio": 4, "resolution_bits": 0 },
+                { "name": "b", "gpio": 5, "resolution_bits": 64 },
+                { "name": "c", "gpio": 6, "frequency": 40000000, "resolution_bits": 14 },
+                { "name": "d", "gpio": 7, "resolution_bits": 14 }
+            ]
+        }"#);
+        assert_eq!(errors.iter().map(|x| x.context.as_str()).collect::<Vec<_>>(), ["pwm_outs a gpio", "pwm_outs b gpio", "pwm_outs c gpio"]);
+
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (_, _, errors) = init(r#"{
+            "pwm_outs": [
+                { "name": "a", "gpio": 4, "resolution_bits": 64 },
+                { "name": "b", "gpio": 5 },
+                { "name": "c", "gpio": 6, "frequency": 2000 },
+                { "name": "d", "gpio": 7, "frequency": 3000 },
+                { "name": "e", "gpio": 8, "frequency": 4000 }
+            ]
+        }"#);
+        // a failed output must not use up a timer
+        assert_eq!(error_contexts(&errors), ["pwm_outs a gpio"]);
+        assert!(matches!(errors[0].error, PeripheralError::PwmResolutionUnsupported { resolution_bits: 64 }));
+    }
+
+    #[test]
+    fn test_buzzer_huge_duration() {
+        let _lock = SIM_LOCK.lock().unwrap();
+        let (handles, _, errors) = init(r#"{ "buzzers": [{ "name": "bz", "gpio": 4 }] }"#);
+        assert!(errors.is_empty());
+
+        let huge = || SimpleValue::Number(Number::new(1e300).unwrap());
+        assert!(matches!(syscall(&handles, "Buzzer.bz.playTone", vec![SimpleValue::Number(Number::new(440.0).unwrap()), huge()]), Some(Err(e)) if e.starts_with("Buzzer.bz.playTone failed: invalid duration")));
+        assert!(matches!(syscall(&handles, "Buzzer.bz.playNotes", vec![SimpleValue::List(vec![SimpleValue::List(vec![SimpleValue::String("A4".into()), huge()])])]), Some(Err(e)) if e.ends_with("but item 1 was invalid")));
+    }
+
+    #[test]
+    fn test_validate_pin_capabilities() {
+        // the esp32s3 has no gpio 22-25, so every kind of pin must be rejected there (as it would be by the hardware)
+        let errors = validate_config(r#"{
+            "i2c": { "name": "bus", "gpio_sda": 22, "gpio_scl": 1 },
+            "spi": { "gpio_sclk": 2, "gpio_mosi": 23 },
+            "digital_ins": [{ "name": "btn", "gpio": 24, "negated": false }],
+            "digital_outs": [{ "name": "led", "gpio": 25, "negated": false }],
+            "pwm_outs": [{ "name": "pwm", "gpio": 22 }],
+            "neopixels": [{ "name": "strip", "gpio": 21, "count": 8 }]
+        }"#);
+        assert_eq!(errors.iter().map(|x| x.context.as_str()).collect::<Vec<_>>(), ["I2C bus gpio_sda", "SPI gpio_mosi", "digital_ins btn gpio", "digital_outs led gpio", "pwm_outs pwm gpio"]);
+        assert!(errors.iter().all(|x| x.error.starts_with("PinInsufficientCapability")));
+    }
+
+    #[test]
+    fn test_digital_pull_and_drive() {
+        let errors = validate_config(r#"{
+            "digital_ins": [{ "name": "btn", "gpio": 22, "negated": false, "pull": "up" }],
+            "digital_outs": [
+                { "name": "a", "gpio": 4, "negated": false, "drive": "openDrain" },
+                { "name": "b", "gpio": 5, "negated": false, "drive": "push_pull" },
+                { "name": "c", "gpio": 23, "negated": false, "drive": "open_drain" }
+            ]
+        }"#);
+        assert_eq!(errors.iter().map(|x| x.context.as_str()).collect::<Vec<_>>(), ["digital_ins btn", "digital_ins btn gpio", "digital_outs c", "digital_outs c gpio"]);
+    }
 }
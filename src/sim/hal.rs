@@ -0,0 +1,1052 @@
+//! Simulated stand-ins for the subset of `esp_idf_hal` used by the firmware.
+//! Like their esp-idf counterparts, the drivers in here implement the relevant `embedded_hal` traits,
+//! so they also serve as in-memory fakes for the hardware-independent controllers in [`crate::platform`].
+//!
+//! GPIO levels, analog voltages, and I2C device registers are kept in process-wide tables so that
+//! the simulator console (see [`super::spawn_console`]) can drive inputs and sensor readings.
+
+use std::sync::Mutex;
+
+pub mod peripheral {
+    /// Mirrors `esp_idf_hal::peripheral::Peripheral` so that both owned and borrowed pins can be passed to drivers.
+    pub trait Peripheral {
+        type P;
+        fn pin_number(&self) -> u8;
+    }
+    impl<T: Peripheral> Peripheral for &mut T {
+        type P = T::P;
+        fn pin_number(&self) -> u8 {
+            (**self).pin_number()
+        }
+    }
+}
+
+pub mod io {
+    use super::super::sys::EspError;
+
+    #[derive(Debug)]
+    pub struct EspIOError(pub EspError);
+}
+
+pub mod units {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Hertz(pub u32);
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct KiloHertz(pub u32);
+
+    impl From<KiloHertz> for Hertz {
+        fn from(value: KiloHertz) -> Self {
+            Hertz(value.0 * 1000)
+        }
+    }
+
+    #[allow(non_snake_case)]
+    pub trait FromValueType {
+        fn Hz(self) -> Hertz;
+        fn kHz(self) -> KiloHertz;
+    }
+    impl FromValueType for u32 {
+        fn Hz(self) -> Hertz { Hertz(self) }
+        fn kHz(self) -> KiloHertz { KiloHertz(self) }
+    }
+}
+
+pub mod delay {
+    use std::time::Duration;
+    use std::thread;
+
+    pub struct Ets;
+    impl Ets {
+        pub fn delay_us(us: u32) {
+            thread::sleep(Duration::from_micros(us as u64));
+        }
+        pub fn delay_ms(ms: u32) {
+            thread::sleep(Duration::from_millis(ms as u64));
+        }
+    }
+    impl embedded_hal::delay::DelayNs for Ets {
+        fn delay_ns(&mut self, ns: u32) {
+            thread::sleep(Duration::from_nanos(ns as u64));
+        }
+    }
+
+    /// Mirrors `esp_idf_hal::delay::TickType` with a 1 kHz tick rate (the esp-idf default).
+    pub struct TickType(pub u32);
+    impl TickType {
+        pub const fn new_millis(ms: u64) -> Self {
+            Self(ms as u32)
+        }
+        pub const fn ticks(&self) -> u32 {
+            self.0
+        }
+    }
+}
+
+pub mod task {
+    pub mod notification {
+        use std::num::NonZeroU32;
+        use std::sync::{Arc, Mutex, Condvar};
+        use std::time::Duration;
+
+        /// Like its esp-idf counterpart, notifications are accumulated as bits until the next wait.
+        pub struct Notifier {
+            bits: Mutex<u32>,
+            condvar: Condvar,
+        }
+        impl Notifier {
+            pub unsafe fn notify_and_yield(&self, value: NonZeroU32) -> bool {
+                *self.bits.lock().unwrap() |= value.get();
+                self.condvar.notify_all();
+                true
+            }
+        }
+
+        pub struct Notification(Arc<Notifier>);
+        impl Notification {
+            #[allow(clippy::new_without_default)]
+            pub fn new() -> Self {
+                Self(Arc::new(Notifier { bits: Mutex::new(0), condvar: Condvar::new() }))
+            }
+            pub fn notifier(&self) -> Arc<Notifier> {
+                self.0.clone()
+            }
+            pub fn wait(&self, timeout: u32) -> Option<NonZeroU32> {
+                let bits = self.0.bits.lock().unwrap();
+                let (mut bits, _) = self.0.condvar.wait_timeout_while(bits, Duration::from_millis(timeout as u64), |x| *x == 0).unwrap();
+                NonZeroU32::new(std::mem::take(&mut *bits))
+            }
+        }
+    }
+}
+
+pub mod modem {
+    pub struct Modem;
+
+    pub struct WifiModem;
+    impl WifiModem {
+        pub unsafe fn new() -> Self {
+            WifiModem
+        }
+    }
+}
+
+// -----------------------------------------------------------------
+
+pub(super) const PIN_COUNT: usize = 49;
+
+pub(super) static PIN_LEVELS: Mutex<[bool; PIN_COUNT]> = Mutex::new([false; PIN_COUNT]);
+pub(super) static PIN_VOLTAGES: Mutex<[u16; PIN_COUNT]> = Mutex::new([0; PIN_COUNT]); // in mV
+static PIN_FAULTS: Mutex<[bool; PIN_COUNT]> = Mutex::new([false; PIN_COUNT]);
+
+/// Makes writes to a pin fail, so that tests can check how hardware errors are reported.
+#[cfg(test)]
+pub(crate) fn set_pin_fault(pin: u8, faulty: bool) {
+    PIN_FAULTS.lock().unwrap()[pin as usize] = faulty;
+}
+
+#[derive(Default)]
+struct PinInterrupt {
+    interrupt_type: Option<gpio::InterruptType>,
+    callback: Option<Box<dyn FnMut() + Send>>,
+    enabled: bool,
+}
+
+static PIN_INTERRUPTS: Mutex<std::collections::BTreeMap<u8, PinInterrupt>> = Mutex::new(std::collections::BTreeMap::new());
+
+/// Drives the level of a pin from outside the firmware (e.g., a button press), triggering its interrupt if needed.
+/// Like on the real hardware, an interrupt is disabled once it triggers until it is enabled again.
+pub(super) fn drive_pin(pin: u8, level: bool) {
+    let old = std::mem::replace(&mut PIN_LEVELS.lock().unwrap()[pin as usize], level);
+    let mut interrupts = PIN_INTERRUPTS.lock().unwrap();
+    let Some(interrupt) = interrupts.get_mut(&pin) else { return };
+    let triggered = match interrupt.interrupt_type {
+        Some(gpio::InterruptType::PosEdge) => !old && level,
+        Some(gpio::InterruptType::NegEdge) => old && !level,
+        Some(gpio::InterruptType::AnyEdge) => old != level,
+        None => false,
+    };
+    if triggered && interrupt.enabled {
+        interrupt.enabled = false;
+        if let Some(callback) = interrupt.callback.as_mut() {
+            callback();
+        }
+    }
+}
+
+pub mod gpio {
+    use std::marker::PhantomData;
+
+    use super::super::sys::EspError;
+    use super::peripheral::Peripheral;
+    use super::{PIN_LEVELS, PIN_FAULTS, PIN_INTERRUPTS, PinInterrupt};
+
+    pub struct Pins;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Level {
+        Low, High,
+    }
+    impl From<bool> for Level {
+        fn from(value: bool) -> Self {
+            if value { Level::High } else { Level::Low }
+        }
+    }
+
+    macro_rules! define_pins {
+        ($($name:ident),*$(,)?) => {$(
+            pub struct $name(u8);
+            impl $name {
+                pub unsafe fn new(pin: i32) -> Self {
+                    Self(pin as u8)
+                }
+                pub fn pin(&self) -> i32 {
+                    self.0 as i32
+                }
+            }
+            impl Peripheral for $name {
+                type P = $name;
+                fn pin_number(&self) -> u8 {
+                    self.0
+                }
+            }
+        )*}
+    }
+    define_pins! { AnyIOPin, AnyInputPin, AnyOutputPin }
+
+    macro_rules! define_fixed_pins {
+        ($($name:ident = $pin:literal),*$(,)?) => {$(
+            pub struct $name;
+            impl $name {
+                pub unsafe fn new() -> Self {
+                    Self
+                }
+                pub fn pin(&self) -> i32 {
+                    $pin
+                }
+            }
+            impl Peripheral for $name {
+                type P = $name;
+                fn pin_number(&self) -> u8 {
+                    $pin
+                }
+            }
+        )*}
+    }
+    define_fixed_pins! { Gpio1 = 1, Gpio2 = 2, Gpio3 = 3, Gpio4 = 4, Gpio5 = 5, Gpio6 = 6, Gpio7 = 7, Gpio8 = 8, Gpio9 = 9, Gpio10 = 10 }
+
+    pub struct Input;
+    pub struct Output;
+    pub struct InputOutput;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Pull {
+        Floating, Up, Down, UpDown,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum InterruptType {
+        PosEdge, NegEdge, AnyEdge,
+    }
+
+    pub struct PinDriver<'d, T, MODE> {
+        pin: u8,
+        _phantom: PhantomData<(&'d mut T, MODE)>,
+    }
+    impl<'d, T, MODE> PinDriver<'d, T, MODE> {
+        fn wrap(pin: u8) -> Self {
+            Self { pin, _phantom: PhantomData }
+        }
+        pub fn pin(&self) -> i32 {
+            self.pin as i32
+        }
+        pub fn get_level(&self) -> Level {
+            PIN_LEVELS.lock().unwrap()[self.pin as usize].into()
+        }
+        pub fn is_high(&self) -> bool {
+            self.get_level() == Level::High
+        }
+        pub fn is_low(&self) -> bool {
+            self.get_level() == Level::Low
+        }
+        pub fn set_level(&mut self, level: Level) -> Result<(), EspError> {
+            if PIN_FAULTS.lock().unwrap()[self.pin as usize] {
+                return Err(EspError::fail());
+            }
+            let mut levels = PIN_LEVELS.lock().unwrap();
+            let value = level == Level::High;
+            if levels[self.pin as usize] != value {
+                println!("[sim] gpio {} -> {level:?}", self.pin);
+                levels[self.pin as usize] = value;
+            }
+            Ok(())
+        }
+        pub fn set_high(&mut self) -> Result<(), EspError> {
+            self.set_level(Level::High)
+        }
+        pub fn set_low(&mut self) -> Result<(), EspError> {
+            self.set_level(Level::Low)
+        }
+    }
+    impl embedded_hal::digital::Error for EspError {
+        fn kind(&self) -> embedded_hal::digital::ErrorKind {
+            embedded_hal::digital::ErrorKind::Other
+        }
+    }
+    impl<T, MODE> embedded_hal::digital::ErrorType for PinDriver<'_, T, MODE> {
+        type Error = EspError;
+    }
+    impl<T> embedded_hal::digital::InputPin for PinDriver<'_, T, Input> {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(PinDriver::is_high(self))
+        }
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(PinDriver::is_low(self))
+        }
+    }
+    impl<T> embedded_hal::digital::OutputPin for PinDriver<'_, T, Output> {
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.set_level(Level::High)
+        }
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set_level(Level::Low)
+        }
+    }
+
+    impl<'d, T> PinDriver<'d, T, Input> {
+        pub fn input(pin: impl Peripheral<P = T> + 'd) -> Result<Self, EspError> {
+            Ok(Self::wrap(pin.pin_number()))
+        }
+        /// Since nothing else drives simulated pins, a pull resistor simply sets the level of the pin.
+        pub fn set_pull(&mut self, pull: Pull) -> Result<(), EspError> {
+            match pull {
+                Pull::Up => super::drive_pin(self.pin, true),
+                Pull::Down => super::drive_pin(self.pin, false),
+                Pull::Floating | Pull::UpDown => (),
+            }
+            Ok(())
+        }
+        pub fn set_interrupt_type(&mut self, interrupt_type: InterruptType) -> Result<(), EspError> {
+            PIN_INTERRUPTS.lock().unwrap().entry(self.pin).or_insert_with(PinInterrupt::default).interrupt_type = Some(interrupt_type);
+            Ok(())
+        }
+        pub unsafe fn subscribe<F: FnMut() + Send + 'static>(&mut self, callback: F) -> Result<(), EspError> {
+            PIN_INTERRUPTS.lock().unwrap().entry(self.pin).or_insert_with(PinInterrupt::default).callback = Some(Box::new(callback));
+            Ok(())
+        }
+        pub fn unsubscribe(&mut self) -> Result<(), EspError> {
+            PIN_INTERRUPTS.lock().unwrap().remove(&self.pin);
+            Ok(())
+        }
+        pub fn enable_interrupt(&mut self) -> Result<(), EspError> {
+            PIN_INTERRUPTS.lock().unwrap().entry(self.pin).or_insert_with(PinInterrupt::default).enabled = true;
+            Ok(())
+        }
+    }
+    impl<'d, T> PinDriver<'d, T, Output> {
+        pub fn output(pin: impl Peripheral<P = T> + 'd) -> Result<Self, EspError> {
+            Ok(Self::wrap(pin.pin_number()))
+        }
+        pub fn output_od(pin: impl Peripheral<P = T> + 'd) -> Result<Self, EspError> {
+            Ok(Self::wrap(pin.pin_number()))
+        }
+    }
+    impl<'d, T> PinDriver<'d, T, InputOutput> {
+        pub fn input_output(pin: impl Peripheral<P = T> + 'd) -> Result<Self, EspError> {
+            Ok(Self::wrap(pin.pin_number()))
+        }
+    }
+}
+
+pub mod ledc {
+    use std::borrow::Borrow;
+    use std::marker::PhantomData;
+
+    use super::super::sys::EspError;
+    use super::peripheral::Peripheral;
+
+    pub mod config {
+        use super::super::units::Hertz;
+        use super::Resolution;
+
+        #[derive(Debug, Clone)]
+        pub struct TimerConfig {
+            pub frequency: Hertz,
+            pub resolution: Resolution,
+        }
+        impl Default for TimerConfig {
+            fn default() -> Self {
+                Self { frequency: Hertz(1000), resolution: Resolution::Bits8 }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Resolution {
+        Bits1, Bits2, Bits3, Bits4, Bits5, Bits6, Bits7, Bits8, Bits9, Bits10, Bits11, Bits12, Bits13, Bits14,
+    }
+    impl Resolution {
+        pub fn bits(&self) -> usize {
+            *self as usize + 1
+        }
+        pub fn max_duty(&self) -> u32 {
+            (1 << self.bits()) - 1
+        }
+    }
+
+    pub trait LedcTimer { const INDEX: u8; }
+    pub trait LedcChannel { const INDEX: u8; }
+
+    macro_rules! define_units {
+        ($tr:ident : $($name:ident = $index:literal),*$(,)?) => {$(
+            pub struct $name;
+            impl $tr for $name { const INDEX: u8 = $index; }
+            impl $name {
+                /// Mirrors `esp_idf_hal::peripheral::Peripheral::clone_unchecked`.
+                #[allow(dead_code)]
+                pub unsafe fn clone_unchecked(&mut self) -> Self { Self }
+            }
+        )*}
+    }
+    define_units! { LedcTimer: TIMER0 = 0, TIMER1 = 1, TIMER2 = 2, TIMER3 = 3 }
+    define_units! { LedcChannel: CHANNEL0 = 0, CHANNEL1 = 1, CHANNEL2 = 2, CHANNEL3 = 3, CHANNEL4 = 4, CHANNEL5 = 5, CHANNEL6 = 6, CHANNEL7 = 7 }
+
+    pub struct LEDC {
+        pub timer0: TIMER0,
+        pub timer1: TIMER1,
+        pub timer2: TIMER2,
+        pub timer3: TIMER3,
+        pub channel0: CHANNEL0,
+        pub channel1: CHANNEL1,
+        pub channel2: CHANNEL2,
+        pub channel3: CHANNEL3,
+        pub channel4: CHANNEL4,
+        pub channel5: CHANNEL5,
+        pub channel6: CHANNEL6,
+        pub channel7: CHANNEL7,
+    }
+    impl LEDC {
+        pub(in super::super) fn new() -> Self {
+            Self {
+                timer0: TIMER0, timer1: TIMER1, timer2: TIMER2, timer3: TIMER3,
+                channel0: CHANNEL0, channel1: CHANNEL1, channel2: CHANNEL2, channel3: CHANNEL3,
+                channel4: CHANNEL4, channel5: CHANNEL5, channel6: CHANNEL6, channel7: CHANNEL7,
+            }
+        }
+    }
+
+    pub struct LedcTimerDriver<'d, T> {
+        config: config::TimerConfig,
+        _phantom: PhantomData<&'d mut T>,
+    }
+    impl<'d, T: LedcTimer> LedcTimerDriver<'d, T> {
+        pub fn new(_timer: T, config: &config::TimerConfig) -> Result<Self, EspError> {
+            println!("[sim] ledc timer {} -> {} Hz ({} bits)", T::INDEX, config.frequency.0, config.resolution.bits());
+            Ok(Self { config: config.clone(), _phantom: PhantomData })
+        }
+        pub fn get_max_duty(&self) -> u32 {
+            self.config.resolution.max_duty()
+        }
+    }
+
+    pub struct LedcDriver<'d> {
+        channel: u8,
+        pin: u8,
+        duty: u32,
+        max_duty: u32,
+        _phantom: PhantomData<&'d mut ()>,
+    }
+    impl<'d> LedcDriver<'d> {
+        pub fn new<C: LedcChannel, T: LedcTimer + 'd, B: Borrow<LedcTimerDriver<'d, T>>>(_channel: C, timer_driver: B, pin: impl Peripheral + 'd) -> Result<Self, EspError> {
+            let max_duty = timer_driver.borrow().get_max_duty();
+            Ok(Self { channel: C::INDEX, pin: pin.pin_number(), duty: 0, max_duty, _phantom: PhantomData })
+        }
+        pub fn get_duty(&self) -> u32 {
+            self.duty
+        }
+        pub fn get_max_duty(&self) -> u32 {
+            self.max_duty
+        }
+        pub fn set_duty(&mut self, duty: u32) -> Result<(), EspError> {
+            if duty > self.max_duty {
+                return Err(EspError::fail());
+            }
+            if duty != self.duty {
+                println!("[sim] ledc channel {} (gpio {}) -> duty {duty}/{}", self.channel, self.pin, self.max_duty);
+                self.duty = duty;
+            }
+            Ok(())
+        }
+    }
+    impl embedded_hal::pwm::ErrorType for LedcDriver<'_> {
+        type Error = core::convert::Infallible;
+    }
+    impl embedded_hal::pwm::SetDutyCycle for LedcDriver<'_> {
+        fn max_duty_cycle(&self) -> u16 {
+            self.max_duty.min(u16::MAX as u32) as u16
+        }
+        fn set_duty_cycle(&mut self, duty: u16) -> Result<(), Self::Error> {
+            self.set_duty((duty as u32).min(self.max_duty)).unwrap();
+            Ok(())
+        }
+    }
+}
+
+/// Like the esp32s3, only GPIO1-10 are connected to ADC1.
+/// Simulated pins read the voltage set from the console (see [`super::spawn_console`]), with the same full-scale range as the real chip.
+pub mod rmt {
+    use std::marker::PhantomData;
+    use std::time::Duration;
+    use std::fmt::Write;
+
+    use super::super::sys::EspError;
+    use super::peripheral::Peripheral;
+    use super::units::Hertz;
+
+    const SOURCE_CLOCK_HZ: u32 = 80_000_000;
+
+    pub mod config {
+        #[derive(Debug, Clone)]
+        pub struct TransmitConfig {
+            pub clock_divider: u8,
+        }
+        impl TransmitConfig {
+            pub fn new() -> Self {
+                Self { clock_divider: 80 }
+            }
+            pub fn clock_divider(mut self, divider: u8) -> Self {
+                self.clock_divider = divider;
+                self
+            }
+        }
+        impl Default for TransmitConfig {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PinState {
+        Low, High,
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Pulse {
+        ticks: u32,
+        pin_state: PinState,
+    }
+    impl Pulse {
+        pub fn new_with_duration(ticks_hz: Hertz, pin_state: PinState, duration: &Duration) -> Result<Self, EspError> {
+            let ticks = (ticks_hz.0 as u128 * duration.as_nanos() / 1_000_000_000) as u32;
+            if ticks == 0 || ticks >= 1 << 15 {
+                return Err(EspError::fail());
+            }
+            Ok(Self { ticks, pin_state })
+        }
+    }
+
+    #[derive(Debug, Default)]
+    pub struct VariableLengthSignal {
+        pulses: Vec<Pulse>,
+    }
+    impl VariableLengthSignal {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn push<'p, I: IntoIterator<Item = &'p Pulse>>(&mut self, pulses: I) -> Result<(), EspError> {
+            self.pulses.extend(pulses);
+            Ok(())
+        }
+    }
+
+    pub trait RmtChannel { const INDEX: u8; }
+
+    macro_rules! define_channels {
+        ($($name:ident = $index:literal),*$(,)?) => {$(
+            pub struct $name;
+            impl RmtChannel for $name { const INDEX: u8 = $index; }
+        )*}
+    }
+    define_channels! { CHANNEL0 = 0, CHANNEL1 = 1, CHANNEL2 = 2, CHANNEL3 = 3 }
+
+    pub struct RMT {
+        pub channel0: CHANNEL0,
+        pub channel1: CHANNEL1,
+        pub channel2: CHANNEL2,
+        pub channel3: CHANNEL3,
+    }
+    impl RMT {
+        pub(in super::super) fn new() -> Self {
+            Self { channel0: CHANNEL0, channel1: CHANNEL1, channel2: CHANNEL2, channel3: CHANNEL3 }
+        }
+    }
+
+    pub struct TxRmtDriver<'d> {
+        channel: u8,
+        pin: u8,
+        ticks_hz: Hertz,
+        _phantom: PhantomData<&'d mut ()>,
+    }
+    impl<'d> TxRmtDriver<'d> {
+        pub fn new<C: RmtChannel>(_channel: C, pin: impl Peripheral + 'd, config: &config::TransmitConfig) -> Result<Self, EspError> {
+            if config.clock_divider == 0 {
+                return Err(EspError::fail());
+            }
+            Ok(Self { channel: C::INDEX, pin: pin.pin_number(), ticks_hz: Hertz(SOURCE_CLOCK_HZ / config.clock_divider as u32), _phantom: PhantomData })
+        }
+        pub fn counter_clock(&self) -> Result<Hertz, EspError> {
+            Ok(self.ticks_hz)
+        }
+        /// Logs the transmitted signal, decoded as a sequence of bytes where each bit is a high pulse followed by a low pulse
+        /// and longer high pulses are ones (as used by ws2812 leds).
+        pub fn start_blocking(&mut self, signal: &VariableLengthSignal) -> Result<(), EspError> {
+            let mut bytes = String::new();
+            for (i, bits) in signal.pulses.chunks(16).enumerate() {
+                let mut byte = 0u8;
+                for bit in bits.chunks(2) {
+                    let one = match bit {
+                        [high, low] if high.pin_state == PinState::High && low.pin_state == PinState::Low => high.ticks > low.ticks,
+                        _ => return Err(EspError::fail()),
+                    };
+                    byte = (byte << 1) | one as u8;
+                }
+                if i % 3 == 0 && i != 0 {
+                    bytes.push(' ');
+                }
+                write!(bytes, "{byte:02x}").unwrap();
+            }
+            println!("[sim] rmt channel {} (gpio {}) -> {bytes}", self.channel, self.pin);
+            Ok(())
+        }
+    }
+}
+
+pub mod adc {
+    use super::gpio::*;
+    use super::peripheral::Peripheral;
+
+    pub mod attenuation {
+        use super::super::super::sys::adc_atten_t;
+
+        pub const NONE: adc_atten_t = 0;
+        pub const DB_2_5: adc_atten_t = 1;
+        pub const DB_6: adc_atten_t = 2;
+        pub const DB_11: adc_atten_t = 3;
+    }
+
+    pub struct ADC1;
+    impl Peripheral for ADC1 {
+        type P = ADC1;
+        fn pin_number(&self) -> u8 {
+            0
+        }
+    }
+
+    pub trait ADCPin: Peripheral<P = Self> {
+        type Adc;
+    }
+    macro_rules! impl_adc_pins {
+        ($adc:ident : $($name:ident),*$(,)?) => {$(
+            impl ADCPin for $name { type Adc = $adc; }
+        )*}
+    }
+    impl_adc_pins! { ADC1: Gpio1, Gpio2, Gpio3, Gpio4, Gpio5, Gpio6, Gpio7, Gpio8, Gpio9, Gpio10 }
+
+    pub mod oneshot {
+        use std::borrow::Borrow;
+        use std::marker::PhantomData;
+
+        use super::super::super::sys::EspError;
+        use super::super::peripheral::Peripheral;
+        use super::super::PIN_VOLTAGES;
+        use super::{ADCPin, attenuation};
+
+        pub mod config {
+            use super::super::super::super::sys::adc_atten_t;
+            use super::super::attenuation;
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            pub enum Calibration {
+                None, Line, Curve,
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+            pub enum Resolution {
+                Resolution12Bit,
+            }
+
+            #[derive(Debug, Clone)]
+            pub struct AdcChannelConfig {
+                pub attenuation: adc_atten_t,
+                pub resolution: Resolution,
+                pub calibration: Calibration,
+            }
+            impl Default for AdcChannelConfig {
+                fn default() -> Self {
+                    Self { attenuation: attenuation::NONE, resolution: Resolution::Resolution12Bit, calibration: Calibration::None }
+                }
+            }
+        }
+
+        pub struct AdcDriver<'d, ADC> {
+            _phantom: PhantomData<&'d mut ADC>,
+        }
+        impl<'d, ADC> AdcDriver<'d, ADC> {
+            pub fn new(_adc: impl Peripheral<P = ADC> + 'd) -> Result<Self, EspError> {
+                Ok(Self { _phantom: PhantomData })
+            }
+        }
+
+        pub struct AdcChannelDriver<'d, T: ADCPin, M: Borrow<AdcDriver<'d, T::Adc>>> {
+            pin: u8,
+            config: config::AdcChannelConfig,
+            _adc: M,
+            _phantom: PhantomData<&'d mut T>,
+        }
+        impl<'d, T: ADCPin, M: Borrow<AdcDriver<'d, T::Adc>>> AdcChannelDriver<'d, T, M> {
+            pub fn new(adc: M, pin: impl Peripheral<P = T> + 'd, config: &config::AdcChannelConfig) -> Result<Self, EspError> {
+                Ok(Self { pin: pin.pin_number(), config: config.clone(), _adc: adc, _phantom: PhantomData })
+            }
+            fn full_scale_mv(&self) -> u32 {
+                match self.config.attenuation {
+                    attenuation::NONE => 950,
+                    attenuation::DB_2_5 => 1250,
+                    attenuation::DB_6 => 1750,
+                    _ => 3100,
+                }
+            }
+            /// Reads the raw 12-bit conversion result.
+            pub fn read_raw(&mut self) -> Result<u16, EspError> {
+                let mv = PIN_VOLTAGES.lock().unwrap()[self.pin as usize] as u32;
+                Ok((mv.min(self.full_scale_mv()) * 4095 / self.full_scale_mv()) as u16)
+            }
+            /// Reads the calibrated voltage in mV (or the raw value if calibration is disabled).
+            pub fn read(&mut self) -> Result<u16, EspError> {
+                let raw = self.read_raw()?;
+                Ok(match self.config.calibration {
+                    config::Calibration::None => raw,
+                    _ => (raw as u32 * self.full_scale_mv() / 4095) as u16,
+                })
+            }
+        }
+    }
+}
+
+// -----------------------------------------------------------------
+
+pub(super) struct SimI2cDevice {
+    pub(super) registers: [u8; 256],
+    pointer: u8,
+}
+impl SimI2cDevice {
+    fn new() -> Self {
+        let mut registers = [0; 256];
+        registers[0x00] = 0x50; // BMP388 chip id
+        registers[0x0f] = 0x33; // LIS3DH WHO_AM_I
+        Self { registers, pointer: 0 }
+    }
+}
+
+/// The simulated I2C buses (keyed by port and address) - every address responds with a 256-byte register file.
+/// The only exception is an empty write (i.e., a bus scan probe), which is only acknowledged by devices that have already been accessed.
+pub(super) static I2C_DEVICES: Mutex<std::collections::BTreeMap<(u8, u8), SimI2cDevice>> = Mutex::new(std::collections::BTreeMap::new());
+
+pub(super) fn with_i2c_device<T>(port: u8, address: u8, f: impl FnOnce(&mut SimI2cDevice) -> T) -> T {
+    f(I2C_DEVICES.lock().unwrap().entry((port, address)).or_insert_with(SimI2cDevice::new))
+}
+
+pub mod i2c {
+    use std::marker::PhantomData;
+
+    use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, SevenBitAddress, NoAcknowledgeSource};
+
+    use super::super::sys::EspError;
+    use super::peripheral::Peripheral;
+    use super::{with_i2c_device, I2C_DEVICES};
+
+    pub use embedded_hal::i2c::Operation;
+
+    pub mod config {
+        use super::super::units::Hertz;
+
+        #[derive(Debug, Clone)]
+        pub struct Config {
+            pub baudrate: Hertz,
+            pub sda_pullup_enabled: bool,
+            pub scl_pullup_enabled: bool,
+        }
+        impl Config {
+            pub fn new() -> Self {
+                Self::default()
+            }
+            pub fn baudrate(mut self, baudrate: Hertz) -> Self {
+                self.baudrate = baudrate;
+                self
+            }
+            pub fn sda_enable_pullup(mut self, enable: bool) -> Self {
+                self.sda_pullup_enabled = enable;
+                self
+            }
+            pub fn scl_enable_pullup(mut self, enable: bool) -> Self {
+                self.scl_pullup_enabled = enable;
+                self
+            }
+        }
+        impl Default for Config {
+            fn default() -> Self {
+                Self { baudrate: Hertz(1_000_000), sda_pullup_enabled: true, scl_pullup_enabled: true }
+            }
+        }
+    }
+
+    pub trait I2cPeripheral { const PORT: u8; }
+
+    pub struct I2C0;
+    impl I2cPeripheral for I2C0 { const PORT: u8 = 0; }
+
+    pub struct I2C1;
+    impl I2cPeripheral for I2C1 { const PORT: u8 = 1; }
+
+    #[derive(Debug)]
+    pub struct I2cError {
+        kind: ErrorKind,
+        cause: EspError,
+    }
+    impl I2cError {
+        pub fn cause(&self) -> EspError {
+            self.cause
+        }
+    }
+    impl embedded_hal::i2c::Error for I2cError {
+        fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    pub struct I2cDriver<'d> {
+        port: u8,
+        _phantom: PhantomData<&'d mut ()>,
+    }
+    impl<'d> I2cDriver<'d> {
+        pub fn new<I: I2cPeripheral>(_i2c: I, sda: impl Peripheral + 'd, scl: impl Peripheral + 'd, config: &config::Config) -> Result<Self, EspError> {
+            let pullups = if config.sda_pullup_enabled && config.scl_pullup_enabled { "on" } else { "off" };
+            println!("[sim] i2c{} -> sda gpio {}, scl gpio {} ({} Hz, pullups {pullups})", I::PORT, sda.pin_number(), scl.pin_number(), config.baudrate.0);
+            Ok(Self { port: I::PORT, _phantom: PhantomData })
+        }
+        pub fn port(&self) -> u8 {
+            self.port
+        }
+    }
+    impl ErrorType for I2cDriver<'_> {
+        type Error = I2cError;
+    }
+    impl I2c<SevenBitAddress> for I2cDriver<'_> {
+        fn transaction(&mut self, address: SevenBitAddress, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            if address > 0x7f {
+                return Err(I2cError { kind: ErrorKind::Other, cause: EspError::fail() });
+            }
+            let is_probe = operations.iter().all(|op| matches!(op, Operation::Write(x) if x.is_empty()));
+            if is_probe && !I2C_DEVICES.lock().unwrap().contains_key(&(self.port, address)) {
+                return Err(I2cError { kind: ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address), cause: EspError::fail() });
+            }
+            with_i2c_device(self.port, address, |device| {
+                for op in operations.iter_mut() {
+                    match op {
+                        Operation::Write(data) => {
+                            if let Some((pointer, data)) = data.split_first() {
+                                device.pointer = *pointer;
+                                for value in data {
+                                    device.registers[device.pointer as usize] = *value;
+                                    device.pointer = device.pointer.wrapping_add(1);
+                                }
+                            }
+                        }
+                        Operation::Read(buf) => {
+                            for value in buf.iter_mut() {
+                                *value = device.registers[device.pointer as usize];
+                                device.pointer = device.pointer.wrapping_add(1);
+                            }
+                        }
+                    }
+                }
+            });
+            Ok(())
+        }
+    }
+}
+
+// -----------------------------------------------------------------
+
+pub mod spi {
+    use std::borrow::Borrow;
+    use std::marker::PhantomData;
+
+    use embedded_hal::spi::{ErrorKind, ErrorType, SpiDevice, Operation};
+
+    use super::super::sys::EspError;
+    use super::peripheral::Peripheral;
+
+    pub mod config {
+        use super::super::units::Hertz;
+
+        #[derive(Debug, Clone, Copy)]
+        pub enum Dma {
+            Disabled,
+            Auto(usize),
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct DriverConfig {
+            pub dma: Dma,
+        }
+        impl DriverConfig {
+            pub fn new() -> Self {
+                Self { dma: Dma::Disabled }
+            }
+            pub fn dma(mut self, dma: Dma) -> Self {
+                self.dma = dma;
+                self
+            }
+        }
+        impl Default for DriverConfig {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct Config {
+            pub baudrate: Hertz,
+        }
+        impl Config {
+            pub fn new() -> Self {
+                Self { baudrate: Hertz(1_000_000) }
+            }
+            pub fn baudrate(mut self, baudrate: Hertz) -> Self {
+                self.baudrate = baudrate;
+                self
+            }
+        }
+        impl Default for Config {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+    }
+
+    pub trait SpiAnyPins { const PORT: u8; }
+
+    pub struct SPI2;
+    impl SpiAnyPins for SPI2 { const PORT: u8 = 2; }
+
+    #[derive(Debug)]
+    pub struct SpiError {
+        kind: ErrorKind,
+        cause: EspError,
+    }
+    impl SpiError {
+        pub fn cause(&self) -> EspError {
+            self.cause
+        }
+    }
+    impl embedded_hal::spi::Error for SpiError {
+        fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    pub struct SpiDriver<'d> {
+        port: u8,
+        _phantom: PhantomData<&'d mut ()>,
+    }
+    impl<'d> SpiDriver<'d> {
+        pub fn new<S: SpiAnyPins>(_spi: S, sclk: impl Peripheral + 'd, sdo: impl Peripheral + 'd, sdi: Option<impl Peripheral + 'd>, _config: &config::DriverConfig) -> Result<Self, EspError> {
+            let sdi = sdi.map(|x| format!("gpio {}", x.pin_number())).unwrap_or_else(|| "none".into());
+            println!("[sim] spi{} -> sclk gpio {}, mosi gpio {}, miso {sdi}", S::PORT, sclk.pin_number(), sdo.pin_number());
+            Ok(Self { port: S::PORT, _phantom: PhantomData })
+        }
+        pub fn port(&self) -> u8 {
+            self.port
+        }
+    }
+
+    /// A device on a (possibly shared) spi bus - writes are logged and reads return all ones (i.e., nothing is driving miso).
+    pub struct SpiDeviceDriver<'d, T> {
+        driver: T,
+        cs: Option<u8>,
+        _phantom: PhantomData<&'d mut ()>,
+    }
+    impl<'d, T: Borrow<SpiDriver<'d>>> SpiDeviceDriver<'d, T> {
+        pub fn new(driver: T, cs: Option<impl Peripheral + 'd>, _config: &config::Config) -> Result<Self, EspError> {
+            Ok(Self { driver, cs: cs.map(|x| x.pin_number()), _phantom: PhantomData })
+        }
+    }
+    impl<T> ErrorType for SpiDeviceDriver<'_, T> {
+        type Error = SpiError;
+    }
+    impl<'d, T: Borrow<SpiDriver<'d>>> SpiDevice for SpiDeviceDriver<'d, T> {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+            let cs = self.cs.map(|x| format!("gpio {x}")).unwrap_or_else(|| "none".into());
+            for op in operations.iter_mut() {
+                let written = match op {
+                    Operation::Write(data) => Some(&**data),
+                    Operation::Transfer(read, write) => {
+                        read.fill(0xff);
+                        Some(&**write)
+                    }
+                    Operation::TransferInPlace(data) => {
+                        println!("[sim] spi{} (cs {cs}) <- {} bytes", self.driver.borrow().port(), data.len());
+                        data.fill(0xff);
+                        None
+                    }
+                    Operation::Read(data) => {
+                        data.fill(0xff);
+                        None
+                    }
+                    Operation::DelayNs(_) => None,
+                };
+                if let Some(data) = written {
+                    let preview = data.iter().take(16).map(|x| format!("{x:02x}")).collect::<Vec<_>>().join(" ");
+                    let more = if data.len() > 16 { format!(" ... ({} bytes)", data.len()) } else { String::new() };
+                    println!("[sim] spi{} (cs {cs}) <- {preview}{more}", self.driver.borrow().port());
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+// -----------------------------------------------------------------
+
+pub mod peripherals {
+    use super::super::sys::EspError;
+    use super::{gpio, ledc, rmt, adc, i2c, spi, modem};
+
+    pub struct Peripherals {
+        pub pins: gpio::Pins,
+        pub ledc: ledc::LEDC,
+        pub rmt: rmt::RMT,
+        pub adc1: adc::ADC1,
+        pub i2c0: i2c::I2C0,
+        pub i2c1: i2c::I2C1,
+        pub spi2: spi::SPI2,
+        pub modem: modem::Modem,
+    }
+    impl Peripherals {
+        pub fn take() -> Result<Self, EspError> {
+            Ok(unsafe { Self::steal() })
+        }
+        pub unsafe fn steal() -> Self {
+            Self {
+                pins: gpio::Pins,
+                ledc: ledc::LEDC::new(),
+                rmt: rmt::RMT::new(),
+                adc1: adc::ADC1,
+                i2c0: i2c::I2C0,
+                i2c1: i2c::I2C1,
+                spi2: spi::SPI2,
+                modem: modem::Modem,
+            }
+        }
+    }
+}
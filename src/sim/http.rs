@@ -0,0 +1,45 @@
+use std::io::Read;
+
+use embedded_svc::http::Method;
+
+use super::sys::EspError;
+
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+}
+
+/// Perform a single http(s) request and returns the response.
+/// This is the host equivalent of the esp-idf client version, and likewise uses a new connection for every request.
+pub fn http_request(method: Method, url: &str, headers: &[(&str, &str)], body: &[u8]) -> Result<Response, EspError> {
+    let method = match method {
+        Method::Get => "GET",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Patch => "PATCH",
+        Method::Head => "HEAD",
+        Method::Options => "OPTIONS",
+        _ => return Err(EspError::fail()),
+    };
+
+    let mut request = ureq::request(method, url);
+    for (k, v) in headers {
+        request = request.set(k, v);
+    }
+
+    let response = match request.send_bytes(body) {
+        Ok(x) => x,
+        Err(ureq::Error::Status(_, x)) => x,
+        Err(ureq::Error::Transport(_)) => return Err(EspError::fail()),
+    };
+
+    let status = response.status();
+    let content_type = response.header("Content-Type").map(ToOwned::to_owned);
+
+    let mut body = vec![];
+    response.into_reader().read_to_end(&mut body)?;
+
+    Ok(Response { status, body, content_type })
+}
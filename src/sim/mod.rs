@@ -0,0 +1,125 @@
+//! Host-side simulator support.
+//!
+//! When building for a non-espidf target, the firmware is compiled against the modules in here instead of the esp-idf crates.
+//! Each of [`sys`], [`hal`], and [`svc`] mirror the (small) subset of their esp-idf counterparts that the firmware uses,
+//! which lets the exact same VM loop, HTTP endpoints, and syscall dispatch run on a development machine or in CI.
+//! Peripherals are simulated: GPIO levels, analog voltages, and I2C device registers can be inspected and driven via [`spawn_console`].
+
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::thread;
+use std::env;
+
+pub mod sys;
+pub mod hal;
+pub mod svc;
+pub mod wifi;
+pub mod http;
+
+/// Gets the port that the simulated HTTP server listens on (`NB_SIM_PORT`, default 8080).
+pub fn http_port() -> u16 {
+    env::var("NB_SIM_PORT").ok().and_then(|x| x.parse().ok()).unwrap_or(8080)
+}
+
+/// Gets the directory used to back the simulated nvs partition (`NB_SIM_NVS`, default `.sim-nvs`).
+pub fn nvs_dir() -> PathBuf {
+    env::var_os("NB_SIM_NVS").map(Into::into).unwrap_or_else(|| ".sim-nvs".into())
+}
+
+/// Gets the directory used to back filesystems mounted into the simulated vfs (`NB_SIM_VFS`, default `.sim-vfs`).
+pub fn vfs_dir() -> PathBuf {
+    env::var_os("NB_SIM_VFS").map(Into::into).unwrap_or_else(|| ".sim-vfs".into())
+}
+
+/// Gets the host path that a filesystem mounted at `mount_point` is accessed through.
+pub fn vfs_path(mount_point: &str) -> PathBuf {
+    vfs_dir().join(mount_point.trim_start_matches('/'))
+}
+
+fn parse_int(s: &str) -> Option<u8> {
+    match s.strip_prefix("0x") {
+        Some(x) => u8::from_str_radix(x, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Gets the i2c port addressed by a console command (`i2c` or `i2c0` for the first bus, `i2c1` for the second).
+fn i2c_port(command: &str) -> Option<u8> {
+    match command {
+        "i2c" | "i2c0" => Some(0),
+        "i2c1" => Some(1),
+        _ => None,
+    }
+}
+
+fn handle_console_command(line: &str) -> Result<String, &'static str> {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    match tokens.as_slice() {
+        ["gpio", pin] => {
+            let pin = parse_int(pin).filter(|&x| (x as usize) < hal::PIN_COUNT).ok_or("invalid pin number")?;
+            let level = hal::PIN_LEVELS.lock().unwrap()[pin as usize];
+            Ok(format!("gpio {pin} = {}", if level { "high" } else { "low" }))
+        }
+        ["gpio", pin, level] => {
+            let pin = parse_int(pin).filter(|&x| (x as usize) < hal::PIN_COUNT).ok_or("invalid pin number")?;
+            let level = match *level {
+                "high" | "1" => true,
+                "low" | "0" => false,
+                _ => return Err("expected level high or low"),
+            };
+            hal::drive_pin(pin, level);
+            Ok(format!("gpio {pin} = {}", if level { "high" } else { "low" }))
+        }
+        ["adc", pin] => {
+            let pin = parse_int(pin).filter(|&x| (x as usize) < hal::PIN_COUNT).ok_or("invalid pin number")?;
+            let mv = hal::PIN_VOLTAGES.lock().unwrap()[pin as usize];
+            Ok(format!("adc {pin} = {mv} mV"))
+        }
+        ["adc", pin, mv] => {
+            let pin = parse_int(pin).filter(|&x| (x as usize) < hal::PIN_COUNT).ok_or("invalid pin number")?;
+            let mv = mv.parse::<u16>().map_err(|_| "invalid voltage (expected mV)")?;
+            hal::PIN_VOLTAGES.lock().unwrap()[pin as usize] = mv;
+            Ok(format!("adc {pin} = {mv} mV"))
+        }
+        [bus, addr, reg] if i2c_port(bus).is_some() => {
+            let port = i2c_port(bus).unwrap();
+            let (addr, reg) = (parse_int(addr).ok_or("invalid address")?, parse_int(reg).ok_or("invalid register")?);
+            let value = hal::with_i2c_device(port, addr, |device| device.registers[reg as usize]);
+            Ok(format!("{bus} {addr:#04x} [{reg:#04x}] = {value:#04x}"))
+        }
+        [bus, addr, reg, values @ ..] if i2c_port(bus).is_some() => {
+            let port = i2c_port(bus).unwrap();
+            let (addr, reg) = (parse_int(addr).ok_or("invalid address")?, parse_int(reg).ok_or("invalid register")?);
+            let values = values.iter().map(|x| parse_int(x)).collect::<Option<Vec<_>>>().ok_or("invalid register value")?;
+            hal::with_i2c_device(port, addr, |device| {
+                for (i, value) in values.iter().enumerate() {
+                    device.registers[reg.wrapping_add(i as u8) as usize] = *value;
+                }
+            });
+            Ok(format!("{bus} {addr:#04x} [{reg:#04x}] <- {values:02x?}"))
+        }
+        _ => Err("usage: gpio <pin> [high|low] | adc <pin> [mV] | i2c[1] <addr> <reg> [values...]"),
+    }
+}
+
+/// Spawns a thread that reads simulator commands from stdin, one per line:
+///
+/// - `gpio <pin>` prints the level of a pin, and `gpio <pin> <high|low>` drives it (e.g., to simulate a button press).
+/// - `adc <pin>` prints the voltage (in mV) seen by an analog input, and `adc <pin> <mV>` sets it (e.g., to simulate a potentiometer).
+/// - `i2c <addr> <reg>` prints an I2C device register, and `i2c <addr> <reg> <values...>` writes consecutive registers (e.g., to simulate sensor readings).
+///   Devices on the second I2C bus are accessed the same way with `i2c1` instead of `i2c`.
+pub fn spawn_console() {
+    thread::spawn(|| {
+        for line in std::io::stdin().lock().lines() {
+            let line = match line {
+                Ok(x) => x,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() { continue }
+            match handle_console_command(&line) {
+                Ok(x) => println!("[sim] {x}"),
+                Err(e) => println!("[sim] error: {e}"),
+            }
+        }
+    });
+}
@@ -0,0 +1,455 @@
+//! Host-side stand-ins for the subset of `esp_idf_svc` used by the firmware.
+
+pub mod eventloop {
+    use super::super::sys::EspError;
+
+    #[derive(Clone)]
+    pub struct EspSystemEventLoop;
+    impl EspSystemEventLoop {
+        pub fn take() -> Result<Self, EspError> {
+            Ok(EspSystemEventLoop)
+        }
+    }
+}
+
+pub mod nvs {
+    use std::path::PathBuf;
+    use std::fs;
+
+    use super::super::sys::EspError;
+
+    /// The simulated nvs partition is a directory on the host (see [`super::super::nvs_dir`]).
+    #[derive(Clone)]
+    pub struct EspDefaultNvsPartition {
+        root: PathBuf,
+    }
+    impl EspDefaultNvsPartition {
+        pub fn take() -> Result<Self, EspError> {
+            Ok(Self { root: super::super::nvs_dir() })
+        }
+    }
+
+    /// A simulated nvs namespace, which stores each entry as a separate file.
+    pub struct EspDefaultNvs {
+        dir: PathBuf,
+    }
+    impl EspDefaultNvs {
+        pub fn new(partition: EspDefaultNvsPartition, namespace: &str, _read_write: bool) -> Result<Self, EspError> {
+            let dir = partition.root.join(namespace);
+            fs::create_dir_all(&dir)?;
+            Ok(Self { dir })
+        }
+        fn read(&self, name: &str) -> Result<Option<Vec<u8>>, EspError> {
+            match fs::read(self.dir.join(name)) {
+                Ok(x) => Ok(Some(x)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        }
+        pub fn contains(&self, name: &str) -> Result<bool, EspError> {
+            Ok(self.dir.join(name).is_file())
+        }
+        pub fn remove(&mut self, name: &str) -> Result<bool, EspError> {
+            match fs::remove_file(self.dir.join(name)) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+                Err(e) => Err(e.into()),
+            }
+        }
+        pub fn len(&self, name: &str) -> Result<Option<usize>, EspError> {
+            Ok(self.read(name)?.map(|x| x.len()))
+        }
+        pub fn get_raw<'a>(&self, name: &str, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>, EspError> {
+            match self.read(name)? {
+                Some(x) => {
+                    let buf = buf.get_mut(..x.len()).ok_or_else(EspError::fail)?;
+                    buf.copy_from_slice(&x);
+                    Ok(Some(buf))
+                }
+                None => Ok(None),
+            }
+        }
+        pub fn set_raw(&mut self, name: &str, buf: &[u8]) -> Result<bool, EspError> {
+            fs::write(self.dir.join(name), buf)?;
+            Ok(true)
+        }
+    }
+}
+
+pub mod sd {
+    use super::super::sys::EspError;
+
+    pub mod spi {
+        use std::borrow::Borrow;
+        use std::marker::PhantomData;
+
+        use super::super::super::sys::EspError;
+        use super::super::super::hal::peripheral::Peripheral;
+        use super::super::super::hal::spi::SpiDriver;
+
+        pub struct SdSpiHostDriver<'d, T> {
+            _driver: T,
+            pub(in super::super) cs: Option<u8>,
+            _phantom: PhantomData<&'d mut ()>,
+        }
+        impl<'d, T: Borrow<SpiDriver<'d>>> SdSpiHostDriver<'d, T> {
+            pub fn new(driver: T, cs: Option<impl Peripheral + 'd>, _cd: Option<impl Peripheral + 'd>, _wp: Option<impl Peripheral + 'd>, _int: Option<impl Peripheral + 'd>, _wp_active_high: Option<bool>) -> Result<Self, EspError> {
+                Ok(Self { _driver: driver, cs: cs.map(|x| x.pin_number()), _phantom: PhantomData })
+            }
+        }
+    }
+
+    #[derive(Default)]
+    pub struct SdCardConfiguration;
+    impl SdCardConfiguration {
+        pub fn new() -> Self {
+            Self
+        }
+    }
+
+    /// A simulated sd card, which always contains a blank card.
+    pub struct SdCardDriver<T> {
+        _host: T,
+    }
+    impl<'d, T> SdCardDriver<spi::SdSpiHostDriver<'d, T>> {
+        pub fn new_spi(host: spi::SdSpiHostDriver<'d, T>, _config: &SdCardConfiguration) -> Result<Self, EspError> {
+            println!("[sim] sd card (cs {}) detected", host.cs.map(|x| format!("gpio {x}")).unwrap_or_else(|| "none".into()));
+            Ok(Self { _host: host })
+        }
+    }
+}
+
+pub mod fs {
+    pub mod fatfs {
+        use super::super::super::sys::EspError;
+
+        pub struct Fatfs<T> {
+            _driver: T,
+            pub(in super::super) drive: u8,
+        }
+        impl<T> Fatfs<T> {
+            pub fn new_sdcard(drive: u8, driver: T) -> Result<Self, EspError> {
+                Ok(Self { _driver: driver, drive })
+            }
+        }
+    }
+}
+
+pub mod io {
+    pub mod vfs {
+        use std::fs;
+
+        use super::super::super::sys::EspError;
+        use super::super::fs::fatfs::Fatfs;
+
+        /// A filesystem mounted into the simulated vfs, which is a directory on the host (see [`super::super::super::vfs_path`]).
+        pub struct MountedFatfs<T> {
+            _fatfs: T,
+        }
+        impl<T> MountedFatfs<Fatfs<T>> {
+            pub fn mount(fatfs: Fatfs<T>, path: &str, _max_fds: usize) -> Result<Self, EspError> {
+                let dir = super::super::super::vfs_path(path);
+                fs::create_dir_all(&dir)?;
+                println!("[sim] fatfs drive {} mounted at {path} -> {}", fatfs.drive, dir.display());
+                Ok(Self { _fatfs: fatfs })
+            }
+        }
+    }
+}
+
+pub mod sntp {
+    use super::super::sys::EspError;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum SyncMode {
+        #[default] Smooth,
+        Immediate,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SyncStatus {
+        Reset, Completed, InProgress,
+    }
+
+    #[derive(Debug, Clone, Default)]
+    pub struct SntpConf {
+        pub sync_mode: SyncMode,
+    }
+
+    /// The host clock is assumed to already be synchronized, so this is always [`SyncStatus::Completed`].
+    pub struct EspSntp;
+    impl EspSntp {
+        pub fn new(_conf: &SntpConf) -> Result<Self, EspError> {
+            Ok(EspSntp)
+        }
+        pub fn get_sync_status(&self) -> SyncStatus {
+            SyncStatus::Completed
+        }
+    }
+}
+
+pub mod http {
+    pub mod server {
+        use std::io::{BufRead, BufReader, Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::sync::{Arc, Mutex};
+        use std::fmt::Debug;
+        use std::thread;
+
+        use embedded_svc::http::Method;
+
+        use super::super::super::sys::EspError;
+
+        /// Mirrors `embedded_svc::http::server::Handler` for the simulated connection type.
+        pub trait Handler<C> {
+            type Error: Debug;
+            fn handle(&self, connection: &mut C) -> Result<(), Self::Error>;
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct Configuration {
+            pub http_port: u16,
+        }
+        impl Default for Configuration {
+            fn default() -> Self {
+                Self { http_port: super::super::super::http_port() }
+            }
+        }
+
+        pub struct EspHttpConnection<'a> {
+            stream: &'a mut TcpStream,
+            uri: String,
+            headers: Vec<(String, String)>,
+            body: Vec<u8>,
+            body_pos: usize,
+            response_initiated: bool,
+        }
+        impl EspHttpConnection<'_> {
+            pub fn uri(&self) -> &str {
+                &self.uri
+            }
+            pub fn header(&self, name: &str) -> Option<&str> {
+                self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+            }
+            pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, EspError> {
+                let len = (self.body.len() - self.body_pos).min(buf.len());
+                buf[..len].copy_from_slice(&self.body[self.body_pos..self.body_pos + len]);
+                self.body_pos += len;
+                Ok(len)
+            }
+            pub fn initiate_response(&mut self, status: u16, message: Option<&str>, headers: &[(&str, &str)]) -> Result<(), EspError> {
+                if self.response_initiated {
+                    return Err(EspError::fail());
+                }
+                self.response_initiated = true;
+
+                write!(self.stream, "HTTP/1.1 {status} {}\r\nConnection: close\r\n", message.unwrap_or(if (200..300).contains(&status) { "OK" } else { "Error" }))?;
+                for (k, v) in headers {
+                    write!(self.stream, "{k}: {v}\r\n")?;
+                }
+                self.stream.write_all(b"\r\n")?;
+                Ok(())
+            }
+            pub fn is_response_initiated(&self) -> bool {
+                self.response_initiated
+            }
+            pub fn write(&mut self, buf: &[u8]) -> Result<usize, EspError> {
+                if !self.response_initiated {
+                    self.initiate_response(200, None, &[])?;
+                }
+                self.stream.write_all(buf)?;
+                Ok(buf.len())
+            }
+        }
+
+        type BoxedHandler = Box<dyn for<'r> Fn(&mut EspHttpConnection<'r>) -> Result<(), String> + Send>;
+
+        /// A minimal single-threaded HTTP/1.1 server (no TLS) that dispatches to handlers just like the esp-idf server.
+        pub struct EspHttpServer {
+            routes: Arc<Mutex<Vec<(String, Method, BoxedHandler)>>>,
+        }
+        impl EspHttpServer {
+            pub fn new(config: &Configuration) -> Result<Self, EspError> {
+                let listener = TcpListener::bind(("0.0.0.0", config.http_port))?;
+                println!("[sim] http server listening on port {}", config.http_port);
+
+                let routes: Arc<Mutex<Vec<(String, Method, BoxedHandler)>>> = Default::default();
+                let routes_clone = routes.clone();
+                thread::spawn(move || {
+                    for stream in listener.incoming() {
+                        let mut stream = match stream {
+                            Ok(x) => x,
+                            Err(_) => continue,
+                        };
+                        if let Err(e) = serve(&mut stream, &routes_clone) {
+                            println!("[sim] http error: {e:?}");
+                        }
+                    }
+                });
+
+                Ok(Self { routes })
+            }
+            pub fn handler<H>(&mut self, uri: &str, method: Method, handler: H) -> Result<&mut Self, EspError> where H: for<'r> Handler<EspHttpConnection<'r>> + Send + 'static {
+                let handler: BoxedHandler = Box::new(move |connection: &mut EspHttpConnection<'_>| handler.handle(connection).map_err(|e| format!("{e:?}")));
+                self.routes.lock().unwrap().push((uri.into(), method, handler));
+                Ok(self)
+            }
+        }
+
+        fn parse_method(method: &str) -> Option<Method> {
+            Some(match method {
+                "GET" => Method::Get,
+                "POST" => Method::Post,
+                "PUT" => Method::Put,
+                "DELETE" => Method::Delete,
+                "HEAD" => Method::Head,
+                "OPTIONS" => Method::Options,
+                "PATCH" => Method::Patch,
+                _ => return None,
+            })
+        }
+
+        fn serve(stream: &mut TcpStream, routes: &Mutex<Vec<(String, Method, BoxedHandler)>>) -> Result<(), EspError> {
+            let mut reader = BufReader::new(stream.try_clone()?);
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line)?;
+            let mut tokens = request_line.split_whitespace();
+            let (method, uri) = match (tokens.next().and_then(parse_method), tokens.next()) {
+                (Some(method), Some(uri)) => (method, uri.to_owned()),
+                _ => return Err(EspError::fail()),
+            };
+
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                let line = line.trim_end();
+                if line.is_empty() { break }
+                if let Some((k, v)) = line.split_once(':') {
+                    headers.push((k.trim().to_owned(), v.trim().to_owned()));
+                }
+            }
+
+            let content_length = headers.iter().find(|(k, _)| k.eq_ignore_ascii_case("Content-Length")).and_then(|(_, v)| v.parse::<usize>().ok()).unwrap_or(0);
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+
+            let path = uri.split('?').next().unwrap_or_default().to_owned();
+            let mut connection = EspHttpConnection { stream, uri, headers, body, body_pos: 0, response_initiated: false };
+
+            let routes = routes.lock().unwrap();
+            match routes.iter().find(|(route, route_method, _)| *route == path && *route_method == method) {
+                Some((_, _, handler)) => {
+                    if let Err(e) = handler(&mut connection) {
+                        println!("[sim] handler for {path} failed: {e}");
+                        if !connection.is_response_initiated() {
+                            connection.initiate_response(500, None, &[("Content-Type", "text/plain")])?;
+                            connection.write(e.as_bytes())?;
+                        }
+                    }
+                }
+                None => {
+                    connection.initiate_response(404, None, &[("Content-Type", "text/plain")])?;
+                    connection.write(b"not found")?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+pub mod ws {
+    pub mod client {
+        use std::sync::mpsc::{channel, Sender, TryRecvError};
+        use std::marker::PhantomData;
+        use std::time::Duration;
+        use std::net::TcpStream;
+        use std::io::ErrorKind;
+        use std::thread;
+
+        use embedded_svc::ws::FrameType;
+
+        use tungstenite::stream::MaybeTlsStream;
+        use tungstenite::Message;
+
+        use super::super::super::sys::EspError;
+        use super::super::super::hal::io::EspIOError;
+
+        #[derive(Debug, Clone, Default)]
+        pub struct EspWebSocketClientConfig {
+            pub task_stack: usize,
+            pub disable_auto_reconnect: bool, // the simulated client never reconnects on its own
+        }
+
+        #[derive(Debug)]
+        pub enum WebSocketEventType<'a> {
+            Connected,
+            Disconnected,
+            Close,
+            Closed,
+            Text(&'a str),
+            Binary(&'a [u8]),
+            Ping,
+            Pong,
+        }
+
+        #[derive(Debug)]
+        pub struct WebSocketEvent<'a> {
+            pub event_type: WebSocketEventType<'a>,
+        }
+
+        /// A websocket client which runs the connection on a background thread, similar to the esp-idf client task.
+        pub struct EspWebSocketClient<'a> {
+            sender: Sender<Message>,
+            _phantom: PhantomData<&'a ()>,
+        }
+        impl<'a> EspWebSocketClient<'a> {
+            pub fn new<F>(uri: &str, _config: &EspWebSocketClientConfig, timeout: Duration, mut callback: F) -> Result<Self, EspIOError> where F: for<'r> FnMut(&'r Result<WebSocketEvent<'r>, EspIOError>) + Send + 'static {
+                let (mut socket, _) = tungstenite::connect(uri).map_err(|_| EspIOError(EspError::fail()))?;
+                let stream = match socket.get_mut() {
+                    MaybeTlsStream::Plain(x) => x,
+                    MaybeTlsStream::Rustls(x) => &mut x.sock,
+                    _ => return Err(EspIOError(EspError::fail())),
+                };
+                set_poll_timeout(stream, timeout.min(Duration::from_millis(10))).map_err(EspIOError)?;
+
+                let (sender, receiver) = channel::<Message>();
+                thread::spawn(move || {
+                    callback(&Ok(WebSocketEvent { event_type: WebSocketEventType::Connected }));
+                    loop {
+                        match receiver.try_recv() {
+                            Ok(msg) => if socket.send(msg).is_err() { break },
+                            Err(TryRecvError::Empty) => (),
+                            Err(TryRecvError::Disconnected) => break,
+                        }
+                        match socket.read() {
+                            Ok(Message::Text(x)) => callback(&Ok(WebSocketEvent { event_type: WebSocketEventType::Text(&x) })),
+                            Ok(Message::Binary(x)) => callback(&Ok(WebSocketEvent { event_type: WebSocketEventType::Binary(&x) })),
+                            Ok(Message::Close(_)) => break,
+                            Ok(_) => (),
+                            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                            Err(_) => break,
+                        }
+                    }
+                    callback(&Ok(WebSocketEvent { event_type: WebSocketEventType::Disconnected }));
+                });
+
+                Ok(Self { sender, _phantom: PhantomData })
+            }
+            pub fn send(&mut self, frame_type: FrameType, frame_data: &[u8]) -> Result<(), EspError> {
+                let msg = match frame_type {
+                    FrameType::Text(_) => Message::Text(String::from_utf8(frame_data.to_owned()).map_err(|_| EspError::fail())?),
+                    FrameType::Binary(_) => Message::Binary(frame_data.to_owned()),
+                    _ => return Err(EspError::fail()),
+                };
+                self.sender.send(msg).map_err(|_| EspError::fail())
+            }
+        }
+
+        fn set_poll_timeout(stream: &mut TcpStream, timeout: Duration) -> Result<(), EspError> {
+            stream.set_read_timeout(Some(timeout))?;
+            Ok(())
+        }
+    }
+}
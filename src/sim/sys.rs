@@ -0,0 +1,60 @@
+use std::fmt;
+
+/// Stand-in for the esp-idf error type.
+/// The simulator only produces these for host-side failures (io errors, unreachable servers, etc.) and faults injected by tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EspError(i32);
+impl EspError {
+    pub(crate) fn fail() -> Self {
+        Self(-1)
+    }
+    pub fn from(code: i32) -> Option<Self> {
+        if code != 0 { Some(Self(code)) } else { None }
+    }
+    pub fn code(&self) -> i32 {
+        self.0
+    }
+}
+impl fmt::Display for EspError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EspError({})", self.0)
+    }
+}
+impl std::error::Error for EspError {}
+
+impl From<std::io::Error> for EspError {
+    fn from(_: std::io::Error) -> Self {
+        Self::fail()
+    }
+}
+
+pub fn link_patches() {}
+
+#[allow(non_camel_case_types)]
+pub type adc_atten_t = u32;
+#[allow(non_camel_case_types)]
+pub type ledc_mode_t = u32;
+#[allow(non_camel_case_types)]
+pub type ledc_timer_t = u32;
+#[allow(non_upper_case_globals)]
+pub const ledc_mode_t_LEDC_LOW_SPEED_MODE: ledc_mode_t = 0;
+
+/// The simulator has no MAC address, so this generates a random (locally administered) one.
+pub unsafe fn esp_efuse_mac_get_default(mac: *mut u8) -> i32 {
+    let mut res = [0u8; 6];
+    getrandom::fill(&mut res).unwrap();
+    res[0] = (res[0] | 0x02) & !0x01;
+    unsafe { std::ptr::copy_nonoverlapping(res.as_ptr(), mac, res.len()) };
+    0
+}
+
+/// Changes the frequency of a (simulated) ledc timer, which just logs the new frequency.
+pub unsafe fn ledc_set_freq(_speed_mode: ledc_mode_t, timer_num: ledc_timer_t, freq_hz: u32) -> i32 {
+    println!("[sim] ledc timer {timer_num} -> {freq_hz} Hz");
+    0
+}
+
+/// Heap usage is not tracked by the simulator, so this always returns zero.
+pub unsafe fn esp_get_free_heap_size() -> u32 { 0 }
+/// Heap usage is not tracked by the simulator, so this always returns zero.
+pub unsafe fn esp_get_free_internal_heap_size() -> u32 { 0 }
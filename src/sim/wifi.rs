@@ -0,0 +1,28 @@
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+
+use super::svc::eventloop::EspSystemEventLoop;
+use super::svc::nvs::EspDefaultNvsPartition;
+use super::hal::modem::WifiModem;
+use super::sys::EspError;
+
+use crate::storage::StorageController;
+
+/// The simulator uses the host's network connection, so the access point and client both live on the loopback address.
+pub struct Wifi {
+    _storage: Arc<Mutex<StorageController>>,
+}
+impl Wifi {
+    pub fn new(_modem: WifiModem, _event_loop: EspSystemEventLoop, _nvs_partition: EspDefaultNvsPartition, storage: Arc<Mutex<StorageController>>) -> Result<Self, EspError> {
+        Ok(Wifi { _storage: storage })
+    }
+    pub fn connect(&mut self) -> Result<(), EspError> {
+        Ok(())
+    }
+    pub fn client_ip(&self) -> Option<Ipv4Addr> {
+        Some(Ipv4Addr::LOCALHOST)
+    }
+    pub fn server_ip(&self) -> Ipv4Addr {
+        Ipv4Addr::LOCALHOST
+    }
+}
@@ -1,9 +1,9 @@
 use std::marker::PhantomData;
 use std::borrow::Cow;
 
-use embedded_svc::storage::RawStorage;
-use esp_idf_svc::nvs::EspDefaultNvs;
-use esp_idf_sys::EspError;
+#[cfg(target_os = "espidf")] use embedded_svc::storage::RawStorage;
+use crate::svc::nvs::EspDefaultNvs;
+use crate::sys::EspError;
 
 pub trait EntryType {
     fn to_bytes(&self) -> Cow<[u8]>;
@@ -92,6 +92,22 @@ impl StorageController {
         wifi_client_ssid (wclssid): String,
         wifi_client_pass (wclpass): String,
 
+        admin_pass (adminpass): String,
+        auth_tokens (authtokens): String,
+
+        client_id (clientid): String,
+
+        tls_cert (tlscert): String,
+        tls_key (tlskey): String,
+        tls_auto_san (tlsautosan): String,
+
+        cloud_url (cloudurl): String,
+        editor_url (editorurl): String,
+        services_url (svcurl): String,
+
+        ota_pending (otapend): String,
+        ota_rollback (otarollbk): String,
+
         peripherals (periph): String,
 
         project (proj): String,
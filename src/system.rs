@@ -1,13 +1,13 @@
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use std::sync::mpsc::{Sender, Receiver, channel};
+use std::time::{Duration, Instant};
+use std::sync::mpsc::{Sender, Receiver, RecvTimeoutError, channel};
 use std::rc::Rc;
 use std::thread;
 
-use esp_idf_svc::ws::client::{EspWebSocketClient, EspWebSocketClientConfig, WebSocketEvent, WebSocketEventType};
-use esp_idf_hal::io::EspIOError;
+use crate::svc::ws::client::{EspWebSocketClient, EspWebSocketClientConfig, WebSocketEvent, WebSocketEventType};
+use crate::hal::io::EspIOError;
 use embedded_svc::ws::FrameType;
 use embedded_svc::http::Method;
 
@@ -27,6 +27,11 @@ use netsblox_vm::vecmap::VecMap;
 use crate::http::*;
 
 const MESSAGE_REPLY_TIMEOUT: Duration = Duration::from_millis(1500);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+const WS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_QUEUED_PACKETS: usize = 64; // max outgoing messages held while disconnected (oldest are dropped first)
 
 fn call_rpc<C: CustomTypes<S>, S: System<C>>(context: &NetsBloxContext, host: Option<&str>, service: &str, rpc: &str, args: &VecMap<CompactString, Json, false>) -> Result<SimpleValue, CompactString> {
     let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
@@ -56,194 +61,352 @@ fn call_rpc<C: CustomTypes<S>, S: System<C>>(context: &NetsBloxContext, host: Op
     }
 }
 
-pub struct EspSystem<C: CustomTypes<Self>> {
-    config: Config<C, Self>,
-    context: Arc<NetsBloxContext>,
-    rng: Mutex<ChaChaRng>,
-    clock: Arc<Clock>,
-
-    rpc_request_sender: Sender<RpcRequest<C, Self>>,
+/// The state of the connection to the NetsBlox cloud.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Running in offline mode (no connection will be attempted).
+    Offline,
+    /// The connection was lost and is being re-established (outgoing messages are queued in the meantime).
+    Reconnecting,
+    /// Connected to the cloud.
+    Connected,
+}
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Offline => "offline",
+            ConnectionState::Reconnecting => "reconnecting",
+            ConnectionState::Connected => "connected",
+        }
+    }
+}
 
-    message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>>,
-    message_sender: Sender<OutgoingMessage>,
-    message_receiver: Receiver<IncomingMessage>,
+enum WsCommand {
+    Send(String),
+    Connected { generation: usize },
+    Disconnected { generation: usize },
+    Stop,
 }
-impl<C: CustomTypes<Self>> EspSystem<C> {
-    pub fn new(base_url: CompactString, project_name: Option<CompactString>, config: Config<C, Self>, clock: Arc<Clock>) -> Self {
-        let default_service_host = {
-            let configuration = parse_json_slice::<BTreeMap<String, Json>>(&http_request(Method::Get, &format!("{base_url}/configuration"), &[], &[]).unwrap().body).unwrap();
-            let services_hosts = configuration["servicesHosts"].as_array().unwrap();
-            services_hosts[0].as_object().unwrap().get("url").unwrap().as_str().unwrap().into()
+
+/// Opens a websocket connection to the cloud.
+/// Events are reported to the websocket thread through `commands` (tagged with `generation` so that stale events from old clients can be ignored).
+fn open_websocket(url: &str, generation: usize, commands: Sender<WsCommand>, msg_in_sender: Sender<IncomingMessage>, message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>>) -> Result<EspWebSocketClient<'static>, EspIOError> {
+    let ws_config = EspWebSocketClientConfig {
+        task_stack: 8000, // default caused stack overflow
+        disable_auto_reconnect: true, // we handle reconnecting ourselves so we can re-register the client
+        ..Default::default()
+    };
+    let ws_on_msg = move |x: &Result<WebSocketEvent, EspIOError>| {
+        let mut msg = match x {
+            Ok(x) => {
+                match x.event_type {
+                    WebSocketEventType::Connected => {
+                        let _ = commands.send(WsCommand::Connected { generation });
+                        return;
+                    }
+                    WebSocketEventType::Disconnected | WebSocketEventType::Closed => {
+                        let _ = commands.send(WsCommand::Disconnected { generation });
+                        return;
+                    }
+                    WebSocketEventType::Text(raw) => {
+                        match parse_json::<BTreeMap<String, Json>>(raw) {
+                            Ok(x) => x,
+                            Err(_) => return,
+                        }
+                    }
+                    _ => return,
+                }
+            }
+            Err(_) => {
+                let _ = commands.send(WsCommand::Disconnected { generation });
+                return;
+            }
         };
 
-        let mut context = NetsBloxContext {
-            base_url,
-            default_service_host,
-            client_id: crate::meta::DEFAULT_CLIENT_ID.into(),
-            project_name: project_name.unwrap_or_else(|| "untitled".into()),
+        match msg.get("type").and_then(Json::as_str).unwrap_or("unknown") {
+            "ping" => { let _ = commands.send(WsCommand::Send(json!({ "type": "pong" }).to_string())); }
+            "message" => {
+                let (msg_type, values) = match (msg.remove("msgType"), msg.remove("content")) {
+                    (Some(Json::String(msg_type)), Some(Json::Object(values))) => (msg_type.into(), values),
+                    _ => return,
+                };
+                if msg_type == "__reply__" {
+                    let (value, reply_key) = match ({ values }.remove("body"), msg.remove("requestId")) {
+                        (Some(value), Some(Json::String(request_id))) => (value, ExternReplyKey { request_id: request_id.into() }),
+                        _ => return,
+                    };
+                    if let Some(entry) = message_replies.lock().unwrap().get_mut(&reply_key) {
+                        if entry.value.is_none() {
+                            entry.value = Some(value);
+                        }
+                    }
+                } else {
+                    let reply_key = match msg.contains_key("requestId") {
+                        true => match (msg.remove("srcId"), msg.remove("requestId")) {
+                            (Some(Json::String(src_id)), Some(Json::String(request_id))) => Some(InternReplyKey { src_id: src_id.into(), request_id: request_id.into() }),
+                            _ => return,
+                        }
+                        false => None,
+                    };
+                    let values = values.into_iter().filter_map(|(k, v)| SimpleValue::from_netsblox_json(v).ok().map(|v| (k.into(), v))).collect();
+                    let _ = msg_in_sender.send(IncomingMessage { msg_type, values, reply_key });
+                }
+            }
+            _ => (),
+        }
+    };
+    EspWebSocketClient::new(url, &ws_config, Duration::from_secs(10), ws_on_msg)
+}
 
-            project_id: Default::default(),
-            role_id: Default::default(),
-            role_name: Default::default(),
-        };
+/// Registers the (already connected) client with the cloud so that it can be addressed by other clients.
+fn register_client_state(base_url: &str, client_id: &str, project_name: &str) -> Result<(), String> {
+    let resp = http_request(Method::Post, &format!("{base_url}/network/{client_id}/state"),
+        &[("Content-Type", "application/json")],
+        json!({
+            "state": {
+                "external": {
+                    "address": project_name,
+                    "appId": "vm",
+                }
+            },
+        }).to_string().as_bytes()
+    ).map_err(|e| format!("failed to register client state: {e:?}"))?;
+    match resp.status {
+        200..=299 => Ok(()),
+        status => Err(format!("failed to register client state: status {status}")),
+    }
+}
 
-        let message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>> = Arc::new(Mutex::new(Default::default()));
+/// Connects to the NetsBlox cloud at `context.base_url` and registers this device as a client.
+/// On success, fills in the remaining fields of `context` and returns the channels for sending and receiving messages.
+/// If `context.default_service_host` is already set, it is used instead of the one advertised by the cloud.
+///
+/// After connecting, the websocket is owned by a background thread which automatically reconnects (with exponential backoff) if the connection is lost.
+/// Outgoing messages are queued while disconnected and `connection` is kept up to date with the current state.
+fn connect(context: &mut NetsBloxContext, message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>>, connection: Arc<Mutex<ConnectionState>>) -> Result<(Sender<OutgoingMessage>, Receiver<IncomingMessage>), String> {
+    if context.default_service_host.is_empty() {
+        context.default_service_host = {
+            let resp = http_request(Method::Get, &format!("{}/configuration", context.base_url), &[], &[]).map_err(|e| format!("failed to get configuration: {e:?}"))?;
+            let configuration = parse_json_slice::<BTreeMap<String, Json>>(&resp.body).map_err(|_| "received ill-formed configuration")?;
+            configuration.get("servicesHosts").and_then(Json::as_array).and_then(|x| x.first()).and_then(Json::as_object)
+                .and_then(|x| x.get("url")).and_then(Json::as_str).ok_or("configuration did not specify a services host")?.into()
+        };
+    }
 
-        let (message_sender, message_receiver, ws_finish_flag) = { // scope these so we deallocate them and save precious memory
-            let (msg_in_sender, msg_in_receiver) = channel::<IncomingMessage>();
-            let (msg_out_sender, msg_out_receiver) = channel::<OutgoingMessage>();
-            let (ws_sender, ws_receiver) = channel::<String>();
-            let finish_flag = Arc::new(());
-
-            let ws_config = EspWebSocketClientConfig {
-                task_stack: 8000, // default caused stack overflow
-                ..Default::default()
-            };
-            let ws_url = format!("{}/network/{}/connect", if let Some(x) = context.base_url.strip_prefix("http") { format!("ws{x}") } else { format!("wss://{}", context.base_url) }, context.client_id);
-            let ws_sender_clone = ws_sender.clone();
-            let message_replies = message_replies.clone();
-            let client_id = context.client_id.clone();
-            let mut finish_flag_clone = Some(finish_flag.clone());
-            let ws_on_msg = move |x: &Result<WebSocketEvent, EspIOError>| {
-                let mut msg = match x {
-                    Ok(x) => {
-                        match x.event_type {
-                            WebSocketEventType::Connected => {
-                                ws_sender_clone.send(json!({ "type": "set-uuid", "clientId": client_id }).to_string()).unwrap();
-                                finish_flag_clone.take();
-                                return;
-                            }
-                            WebSocketEventType::Text(raw) => {
-                                match parse_json::<BTreeMap<String, Json>>(raw) {
-                                    Ok(x) => x,
-                                    Err(_) => return,
+    let (message_sender, message_receiver) = { // scope these so we deallocate them and save precious memory
+        let (msg_in_sender, msg_in_receiver) = channel::<IncomingMessage>();
+        let (msg_out_sender, msg_out_receiver) = channel::<OutgoingMessage>();
+        let (ws_sender, ws_receiver) = channel::<WsCommand>();
+
+        let ws_url = format!("{}/network/{}/connect", if let Some(x) = context.base_url.strip_prefix("http") { format!("ws{x}") } else { format!("wss://{}", context.base_url) }, context.client_id);
+        let ws_sender_clone = ws_sender.clone();
+        let base_url = context.base_url.clone();
+        let client_id = context.client_id.clone();
+        let project_name = context.project_name.clone();
+        let connection_clone = connection.clone();
+        let mut ws_client = Some(open_websocket(&ws_url, 0, ws_sender_clone.clone(), msg_in_sender.clone(), message_replies.clone()).map_err(|e| format!("failed to open websocket: {e:?}"))?);
+
+        thread::spawn(move || {
+            let mut generation = 0;
+            let mut connected = false;
+            let mut pending = VecDeque::<String>::new();
+            let mut backoff = RECONNECT_BACKOFF_MIN;
+            let mut attempt_time = Some(Instant::now()); // when the current client was opened (if it has not connected yet)
+            let mut retry_time = None; // when to open a new client (if the current one was lost)
+
+            loop {
+                let mut lost = false;
+                match ws_receiver.recv_timeout(WS_POLL_INTERVAL) {
+                    Ok(WsCommand::Send(packet)) => {
+                        if pending.len() >= MAX_QUEUED_PACKETS {
+                            pending.pop_front();
+                        }
+                        pending.push_back(packet);
+                    }
+                    Ok(WsCommand::Connected { generation: g }) if g == generation => {
+                        // the server forgets about us when the connection drops, so identify ourselves again before anything else
+                        match ws_client.as_mut().unwrap().send(FrameType::Text(false), json!({ "type": "set-uuid", "clientId": client_id }).to_string().as_bytes()) {
+                            Ok(()) => {
+                                if let Err(e) = register_client_state(&base_url, &client_id, &project_name) {
+                                    println!("{e}");
                                 }
+                                println!("websocket connected");
+                                connected = true;
+                                attempt_time = None;
+                                backoff = RECONNECT_BACKOFF_MIN;
+                                *connection_clone.lock().unwrap() = ConnectionState::Connected;
                             }
-                            _ => return,
+                            Err(_) => lost = true,
                         }
                     }
-                    Err(_) => return,
-                };
+                    Ok(WsCommand::Disconnected { generation: g }) if g == generation => lost = true,
+                    Ok(WsCommand::Connected { .. } | WsCommand::Disconnected { .. }) => (), // stale event from an old client
+                    Ok(WsCommand::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                        *connection_clone.lock().unwrap() = ConnectionState::Offline;
+                        return;
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                }
 
-                match msg.get("type").and_then(Json::as_str).unwrap_or("unknown") {
-                    "ping" => ws_sender_clone.send(json!({ "type": "pong" }).to_string()).unwrap(),
-                    "message" => {
-                        let (msg_type, values) = match (msg.remove("msgType"), msg.remove("content")) {
-                            (Some(Json::String(msg_type)), Some(Json::Object(values))) => (msg_type.into(), values),
-                            _ => return,
-                        };
-                        if msg_type == "__reply__" {
-                            let (value, reply_key) = match ({ values }.remove("body"), msg.remove("requestId")) {
-                                (Some(value), Some(Json::String(request_id))) => (value, ExternReplyKey { request_id: request_id.into() }),
-                                _ => return,
-                            };
-                            if let Some(entry) = message_replies.lock().unwrap().get_mut(&reply_key) {
-                                if entry.value.is_none() {
-                                    entry.value = Some(value);
-                                }
-                            }
-                        } else {
-                            let reply_key = match msg.contains_key("requestId") {
-                                true => match (msg.remove("srcId"), msg.remove("requestId")) {
-                                    (Some(Json::String(src_id)), Some(Json::String(request_id))) => Some(InternReplyKey { src_id: src_id.into(), request_id: request_id.into() }),
-                                    _ => return,
-                                }
-                                false => None,
-                            };
-                            let values = values.into_iter().filter_map(|(k, v)| SimpleValue::from_netsblox_json(v).ok().map(|v| (k.into(), v))).collect();
-                            msg_in_sender.send(IncomingMessage { msg_type, values, reply_key }).unwrap();
+                if connected && !lost {
+                    while let Some(packet) = pending.front() {
+                        if ws_client.as_mut().unwrap().send(FrameType::Text(false), packet.as_bytes()).is_err() {
+                            lost = true;
+                            break;
                         }
+                        pending.pop_front();
                     }
-                    _ => (),
                 }
-            };
-            let mut ws_client = EspWebSocketClient::new(&ws_url, &ws_config, Duration::from_secs(10), ws_on_msg).unwrap();
 
-            thread::spawn(move || {
-                while let Ok(packet) = ws_receiver.recv() {
-                    ws_client.send(FrameType::Text(false), packet.as_bytes()).unwrap();
+                if attempt_time.is_some_and(|x| x.elapsed() > CONNECT_TIMEOUT) {
+                    lost = true;
                 }
-            });
 
-            let project_name = context.project_name.clone();
-            let client_id = context.client_id.clone();
-            let src_id = format_compact!("{project_name}@{client_id}#vm");
-            fn resolve_targets<'a>(targets: &'a mut [CompactString], src_id: &CompactString) -> &'a mut [CompactString] {
-                for target in targets.iter_mut() {
-                    if target == "everyone in room" {
-                        target.clone_from(src_id);
+                if lost && ws_client.is_some() {
+                    println!("websocket disconnected -- reconnecting in {backoff:?}");
+                    *connection_clone.lock().unwrap() = ConnectionState::Reconnecting;
+                    ws_client = None;
+                    connected = false;
+                    attempt_time = None;
+                    retry_time = Some(Instant::now() + backoff);
+                    backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                }
+
+                if ws_client.is_none() && retry_time.is_some_and(|x| Instant::now() >= x) {
+                    generation += 1;
+                    match open_websocket(&ws_url, generation, ws_sender_clone.clone(), msg_in_sender.clone(), message_replies.clone()) {
+                        Ok(x) => {
+                            ws_client = Some(x);
+                            attempt_time = Some(Instant::now());
+                            retry_time = None;
+                        }
+                        Err(e) => {
+                            println!("failed to reopen websocket ({e:?}) -- retrying in {backoff:?}");
+                            retry_time = Some(Instant::now() + backoff);
+                            backoff = (backoff * 2).min(RECONNECT_BACKOFF_MAX);
+                        }
                     }
                 }
-                targets
             }
-            thread::spawn(move || {
-                while let Ok(request) = msg_out_receiver.recv() {
-                    let msg = match request {
-                        OutgoingMessage::Normal { msg_type, values, mut targets } => json!({
-                            "type": "message",
-                            "dstId": resolve_targets(&mut targets, &src_id),
-                            "srcId": src_id,
-                            "msgType": msg_type,
-                            "content": values.into_iter().collect::<BTreeMap<_,_>>(),
-                        }),
-                        OutgoingMessage::Blocking { msg_type, values, mut targets, reply_key } => json!({
-                            "type": "message",
-                            "dstId": resolve_targets(&mut targets, &src_id),
-                            "srcId": src_id,
-                            "msgType": msg_type,
-                            "requestId": reply_key.request_id,
-                            "content": values.into_iter().collect::<BTreeMap<_,_>>(),
-                        }),
-                        OutgoingMessage::Reply { value, reply_key } => json!({
-                            "type": "message",
-                            "dstId": reply_key.src_id,
-                            "msgType": "__reply__",
-                            "requestId": reply_key.request_id,
-                            "content": { "body": value },
-                        }),
-                    };
-                    ws_sender.send(msg.to_string()).unwrap();
+        });
+
+        let src_id = format_compact!("{}@{}#vm", context.project_name, context.client_id);
+        fn resolve_targets<'a>(targets: &'a mut [CompactString], src_id: &CompactString) -> &'a mut [CompactString] {
+            for target in targets.iter_mut() {
+                if target == "everyone in room" {
+                    target.clone_from(src_id);
                 }
-            });
+            }
+            targets
+        }
+        thread::spawn(move || {
+            while let Ok(request) = msg_out_receiver.recv() {
+                let msg = match request {
+                    OutgoingMessage::Normal { msg_type, values, mut targets } => json!({
+                        "type": "message",
+                        "dstId": resolve_targets(&mut targets, &src_id),
+                        "srcId": src_id,
+                        "msgType": msg_type,
+                        "content": values.into_iter().collect::<BTreeMap<_,_>>(),
+                    }),
+                    OutgoingMessage::Blocking { msg_type, values, mut targets, reply_key } => json!({
+                        "type": "message",
+                        "dstId": resolve_targets(&mut targets, &src_id),
+                        "srcId": src_id,
+                        "msgType": msg_type,
+                        "requestId": reply_key.request_id,
+                        "content": values.into_iter().collect::<BTreeMap<_,_>>(),
+                    }),
+                    OutgoingMessage::Reply { value, reply_key } => json!({
+                        "type": "message",
+                        "dstId": reply_key.src_id,
+                        "msgType": "__reply__",
+                        "requestId": reply_key.request_id,
+                        "content": { "body": value },
+                    }),
+                };
+                ws_sender.send(WsCommand::Send(msg.to_string())).unwrap();
+            }
+            let _ = ws_sender.send(WsCommand::Stop); // the system was dropped (or never finished connecting), so shut down the websocket
+        });
 
-            (msg_out_sender, msg_in_receiver, Arc::downgrade(&finish_flag))
-        };
+        (msg_out_sender, msg_in_receiver)
+    };
 
-        while ws_finish_flag.upgrade().is_some() {
-            std::thread::sleep(Duration::from_millis(10));
+    let connect_start = Instant::now();
+    while *connection.lock().unwrap() != ConnectionState::Connected {
+        if connect_start.elapsed() > CONNECT_TIMEOUT {
+            return Err("timed out waiting for websocket connection".into());
         }
+        thread::sleep(Duration::from_millis(10));
+    }
 
-        { // scope these so we deallocate them and save precious memory
-            let resp = http_request(Method::Post, &format!("{}/projects/", context.base_url),
-                &[("Content-Type", "application/json")],
-                json!({
-                    "clientId": context.client_id,
-                    "name": context.project_name,
-                }).to_string().as_bytes()
-            ).unwrap();
-            let meta = parse_json_slice::<BTreeMap<String, Json>>(&resp.body).unwrap();
-            context.project_id = meta["id"].as_str().unwrap().into();
-
-            let roles = meta["roles"].as_object().unwrap();
-            let (first_role_id, first_role_meta) = roles.get_key_value(roles.keys().next().unwrap()).unwrap();
-            let first_role_meta = first_role_meta.as_object().unwrap();
-            context.role_id = first_role_id.into();
-            context.role_name = first_role_meta.get("name").unwrap().as_str().unwrap().into();
-        }
+    { // scope these so we deallocate them and save precious memory
+        let resp = http_request(Method::Post, &format!("{}/projects/", context.base_url),
+            &[("Content-Type", "application/json")],
+            json!({
+                "clientId": context.client_id,
+                "name": context.project_name,
+            }).to_string().as_bytes()
+        ).map_err(|e| format!("failed to create project: {e:?}"))?;
+        let meta = parse_json_slice::<BTreeMap<String, Json>>(&resp.body).map_err(|_| "received ill-formed project metadata")?;
+        context.project_id = meta.get("id").and_then(Json::as_str).ok_or("project metadata did not specify an id")?.into();
+
+        let (first_role_id, first_role_meta) = meta.get("roles").and_then(Json::as_object).and_then(|x| x.iter().next()).ok_or("project metadata did not specify any roles")?;
+        context.role_id = first_role_id.into();
+        context.role_name = first_role_meta.get("name").and_then(Json::as_str).ok_or("project metadata did not specify a role name")?.into();
+    }
 
-        { // scope these so we deallocate them and save precious memory
-            http_request(Method::Post, &format!("{}/network/{}/state", context.base_url, context.client_id),
-                &[("Content-Type", "application/json")],
-                json!({
-                    "state": {
-                        "external": {
-                            "address": context.project_name,
-                            "appId": "vm",
-                        }
-                    },
-                }).to_string().as_bytes()
-            ).unwrap();
-        }
+    Ok((message_sender, message_receiver))
+}
+
+pub struct EspSystem<C: CustomTypes<Self>> {
+    config: Config<C, Self>,
+    context: Arc<NetsBloxContext>,
+    rng: Mutex<ChaChaRng>,
+    clock: Arc<Clock>,
+
+    rpc_request_sender: Sender<RpcRequest<C, Self>>,
+
+    message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>>,
+    message_sender: Option<Sender<OutgoingMessage>>,
+    message_receiver: Option<Receiver<IncomingMessage>>,
+    local_message_receiver: Receiver<IncomingMessage>,
+    connection: Arc<Mutex<ConnectionState>>,
+}
+impl<C: CustomTypes<Self>> EspSystem<C> {
+    /// Creates a new system that connects to the NetsBlox cloud at `base_url`.
+    /// If `base_url` is `None` or the cloud cannot be reached, the system runs in offline mode,
+    /// where everything except RPCs and messaging continues to work as usual.
+    /// If `services_url` is `None`, RPCs are sent to the default services host of the cloud.
+    /// The state of the connection is written to `connection` and kept up to date for the lifetime of the system.
+    /// Messages from `local_message_receiver` (e.g., sent by peripherals) are received alongside those from the cloud, even in offline mode.
+    pub fn new(base_url: Option<CompactString>, services_url: Option<CompactString>, client_id: CompactString, project_name: Option<CompactString>, config: Config<C, Self>, clock: Arc<Clock>, connection: Arc<Mutex<ConnectionState>>, local_message_receiver: Receiver<IncomingMessage>) -> Self {
+        let mut context = NetsBloxContext {
+            base_url: base_url.unwrap_or_default(),
+            default_service_host: services_url.unwrap_or_default(),
+            client_id,
+            project_name: project_name.unwrap_or_else(|| "untitled".into()),
+
+            project_id: Default::default(),
+            role_id: Default::default(),
+            role_name: Default::default(),
+        };
+
+        let message_replies: Arc<Mutex<BTreeMap<ExternReplyKey, ReplyEntry>>> = Arc::new(Mutex::new(Default::default()));
+
+        let (message_sender, message_receiver) = match context.base_url.is_empty() {
+            true => (None, None),
+            false => match connect(&mut context, message_replies.clone(), connection.clone()) {
+                Ok((sender, receiver)) => (Some(sender), Some(receiver)),
+                Err(e) => {
+                    println!("failed to connect to {} ({e}) -- running in offline mode", context.base_url);
+                    *connection.lock().unwrap() = ConnectionState::Offline;
+                    (None, None)
+                }
+            }
+        };
+        let online = message_sender.is_some();
 
         let context = Arc::new(context);
 
@@ -262,13 +425,25 @@ impl<C: CustomTypes<Self>> EspSystem<C> {
         getrandom::fill(&mut seed).expect("failed to generate random seed");
 
         let context_clone = context.clone();
+        let connection_clone = connection.clone();
         let config = config.fallback(&Config {
             request: Some(Rc::new(move |_, key, request, proc| match request {
+                Request::Syscall { name, args } if name == "Network.getState" => {
+                    match args.is_empty() {
+                        true => key.complete(Ok(SimpleValue::String(connection_clone.lock().unwrap().as_str().into()).into())),
+                        false => key.complete(Err(format_compact!("expected 0 args, got {}", args.len()))),
+                    }
+                    RequestStatus::Handled
+                }
                 Request::Rpc { service, rpc, args, host } => match (service.as_str(), rpc.as_str(), args.as_slice()) {
                     ("PublicRoles", "getPublicRoleId", []) => {
                         key.complete(Ok(SimpleValue::String(format_compact!("{}@{}#vm", context_clone.project_name, context_clone.client_id)).into()));
                         RequestStatus::Handled
                     }
+                    _ if !online => {
+                        key.complete(Err("RPCs are not available in offline mode".into()));
+                        RequestStatus::Handled
+                    }
                     _ => {
                         match args.into_iter().map(|(k, v)| Ok((k, v.to_simple()?.into_json()?))).collect::<Result<_,ErrorCause<_,_>>>() {
                             Ok(args) => proc.global_context.borrow().system.rpc_request_sender.send(RpcRequest { service, rpc, args, key, host }).unwrap(),
@@ -283,11 +458,20 @@ impl<C: CustomTypes<Self>> EspSystem<C> {
         });
 
         EspSystem {
-            config, context, message_replies, message_sender, message_receiver, rpc_request_sender, clock,
+            config, context, message_replies, message_sender, message_receiver, local_message_receiver, rpc_request_sender, clock, connection,
             rng: Mutex::new(ChaChaRng::from_seed(seed)),
         }
     }
 
+    /// Checks if the system is connected to the NetsBlox cloud (i.e., not running in offline mode).
+    /// Note that an online system may still be temporarily disconnected (see [`EspSystem::connection_state`]).
+    pub fn is_online(&self) -> bool {
+        self.message_sender.is_some()
+    }
+    /// Gets the current state of the connection to the NetsBlox cloud.
+    pub fn connection_state(&self) -> ConnectionState {
+        *self.connection.lock().unwrap()
+    }
     /// Gets the public id of the running system that can be used to send messages to this client.
     pub fn get_public_id(&self) -> String {
         format!("{}@{}#vm", self.context.project_name, self.context.client_id)
@@ -343,6 +527,7 @@ impl<C: CustomTypes<Self>> System<C> for EspSystem<C> {
     }
 
     fn send_message(&self, msg_type: CompactString, values: VecMap<CompactString, Json, false>, targets: Vec<CompactString>, expect_reply: bool) -> Result<Option<ExternReplyKey>, ErrorCause<C, Self>> {
+        let message_sender = self.message_sender.as_ref().ok_or_else(|| ErrorCause::Custom { msg: "messaging is not available in offline mode".into() })?;
         let (msg, reply_key) = match expect_reply {
             false => (OutgoingMessage::Normal { msg_type, values, targets }, None),
             true => {
@@ -352,7 +537,7 @@ impl<C: CustomTypes<Self>> System<C> for EspSystem<C> {
                 (OutgoingMessage::Blocking { msg_type, values, targets, reply_key: reply_key.clone() }, Some(reply_key))
             }
         };
-        self.message_sender.send(msg).unwrap();
+        message_sender.send(msg).unwrap();
         Ok(reply_key)
     }
     fn poll_reply(&self, key: &ExternReplyKey) -> AsyncResult<Option<Json>> {
@@ -368,9 +553,10 @@ impl<C: CustomTypes<Self>> System<C> for EspSystem<C> {
         AsyncResult::Pending
     }
     fn send_reply(&self, key: InternReplyKey, value: Json) -> Result<(), ErrorCause<C, Self>> {
-        Ok(self.message_sender.send(OutgoingMessage::Reply { value, reply_key: key }).unwrap())
+        let message_sender = self.message_sender.as_ref().ok_or_else(|| ErrorCause::Custom { msg: "messaging is not available in offline mode".into() })?;
+        Ok(message_sender.send(OutgoingMessage::Reply { value, reply_key: key }).unwrap())
     }
     fn receive_message(&self) -> Option<IncomingMessage> {
-        self.message_receiver.try_recv().ok()
+        self.message_receiver.as_ref().and_then(|x| x.try_recv().ok()).or_else(|| self.local_message_receiver.try_recv().ok())
     }
 }